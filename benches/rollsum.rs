@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rdiff::rollsum::{Rollsum, Window};
+
+/// `len` bytes from a fixed linear congruential generator.
+fn pseudo_random(len: usize) -> Vec<u8> {
+    let mut x = 1u32;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        })
+        .collect()
+}
+
+/// `update`, which uses AVX2 or SSE2 where the CPU has them, against the plain
+/// byte loop it falls back to elsewhere.
+fn window(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rollsum");
+    for len in [4 << 10, 16 << 10, 64 << 10, 256 << 10, 1 << 20] {
+        let buf = pseudo_random(len);
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::new("update", len), &buf, |b, buf| {
+            b.iter(|| {
+                let mut rs = Window::new();
+                rs.update(buf);
+                rs.digest()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("update_scalar", len), &buf, |b, buf| {
+            b.iter(|| {
+                let mut rs = Window::new();
+                rs.update_scalar(buf);
+                rs.digest()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, window);
+criterion_main!(benches);
@@ -0,0 +1,128 @@
+/*
+ * librsync's public API, implemented by the rdiff crate's capi package.
+ *
+ * Functions and types have librsync's names, signatures and result codes, so
+ * programs written against librsync can link against this library instead.
+ * Differences:
+ *
+ *  - rs_stats_t's lit_cmdbytes and copy_cmdbytes are always 0.
+ *  - rs_build_hash_table does nothing; signatures are indexed as they load.
+ *  - Tracing and rs_mdfour are not provided.
+ */
+#ifndef LIBRSYNC_H
+#define LIBRSYNC_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <time.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef int64_t rs_long_t;
+
+typedef enum {
+    RS_DELTA_MAGIC = 0x72730236,
+    RS_MD4_SIG_MAGIC = 0x72730136,
+    RS_BLAKE2_SIG_MAGIC = 0x72730137,
+    RS_RK_MD4_SIG_MAGIC = 0x72730146,
+    RS_RK_BLAKE2_SIG_MAGIC = 0x72730147
+} rs_magic_number;
+
+typedef enum rs_result {
+    RS_DONE = 0,
+    RS_BLOCKED = 1,
+    RS_RUNNING = 2,
+    RS_TEST_SKIPPED = 77,
+    RS_IO_ERROR = 100,
+    RS_SYNTAX_ERROR = 101,
+    RS_MEM_ERROR = 102,
+    RS_INPUT_ENDED = 103,
+    RS_BAD_MAGIC = 104,
+    RS_UNIMPLEMENTED = 105,
+    RS_CORRUPT = 106,
+    RS_INTERNAL_ERROR = 107,
+    RS_PARAM_ERROR = 108
+} rs_result;
+
+char const *rs_strerror(rs_result r);
+
+typedef struct rs_stats {
+    char const *op;
+    int lit_cmds;
+    rs_long_t lit_bytes;
+    rs_long_t lit_cmdbytes;
+    rs_long_t copy_cmds, copy_bytes, copy_cmdbytes;
+    rs_long_t sig_cmds, sig_bytes;
+    int false_matches;
+    rs_long_t sig_blocks;
+    size_t block_len;
+    rs_long_t in_bytes;
+    rs_long_t out_bytes;
+    time_t start, end;
+} rs_stats_t;
+
+typedef struct rs_signature rs_signature_t;
+
+void rs_free_sumset(rs_signature_t *);
+void rs_sumset_dump(rs_signature_t const *);
+
+struct rs_buffers_s {
+    char *next_in;
+    size_t avail_in;
+    int eof_in;
+    char *next_out;
+    size_t avail_out;
+};
+typedef struct rs_buffers_s rs_buffers_t;
+
+typedef struct rs_job rs_job_t;
+
+/* Fills in a magic of 0, block_len of 0 and strong_len of 0 (longest) or -1
+ * (shortest safe) for a basis of old_fsize bytes, or unknown size if < 0. */
+rs_result rs_sig_args(rs_long_t old_fsize, rs_magic_number *magic,
+                      size_t *block_len, size_t *strong_len);
+
+rs_result rs_job_iter(rs_job_t *job, rs_buffers_t *buffers);
+const rs_stats_t *rs_job_statistics(rs_job_t *job);
+rs_result rs_job_free(rs_job_t *);
+
+/* Returns NULL if the arguments are invalid. */
+rs_job_t *rs_sig_begin(size_t block_len, size_t strong_len,
+                       rs_magic_number sig_magic);
+/* The signature must outlive the job. Returns NULL if it is NULL or still
+ * loading. */
+rs_job_t *rs_delta_begin(rs_signature_t *);
+/* Sets *sumset right away; the signature can be used once the job is done, and
+ * must be freed with rs_free_sumset even if the job fails. */
+rs_job_t *rs_loadsig_begin(rs_signature_t **);
+
+/* Reads *len bytes of the basis at pos, either into *buf or into a buffer of
+ * its own whose address it stores in *buf, and sets *len to the bytes read. */
+typedef rs_result rs_copy_cb(void *opaque, rs_long_t pos, size_t *len,
+                             void **buf);
+rs_job_t *rs_patch_begin(rs_copy_cb *copy_cb, void *copy_arg);
+
+rs_result rs_build_hash_table(rs_signature_t *sums);
+
+/* An rs_copy_cb for a basis in the FILE * passed as arg. */
+rs_result rs_file_copy_cb(void *arg, rs_long_t pos, size_t *len, void **buf);
+
+/* stats may be NULL. */
+rs_result rs_sig_file(FILE *old_file, FILE *sig_file, size_t block_len,
+                      size_t strong_len, rs_magic_number sig_magic,
+                      rs_stats_t *stats);
+rs_result rs_loadsig_file(FILE *sig_file, rs_signature_t **sumset,
+                          rs_stats_t *stats);
+rs_result rs_delta_file(rs_signature_t *, FILE *new_file, FILE *delta_file,
+                        rs_stats_t *);
+rs_result rs_patch_file(FILE *basis_file, FILE *delta_file, FILE *new_file,
+                        rs_stats_t *);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* !LIBRSYNC_H */
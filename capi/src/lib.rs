@@ -0,0 +1,734 @@
+//! librsync's C API, declared in `include/librsync.h`, implemented with rdiff.
+#![allow(non_camel_case_types, clippy::missing_safety_doc)]
+
+use std::ffi::{c_char, c_int, c_void, CStr};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ptr;
+use std::slice;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use rdiff::delta::generate_delta_from_signature;
+use rdiff::job::{Buffers, Job, JobStatus};
+use rdiff::mksum::{generate_signature, min_strong_len, SignatureFormat, SignatureOptions};
+use rdiff::patch::apply_patch;
+use rdiff::sumset::Signature;
+use rdiff::{Error, Stats, DEFAULT_BLOCK_LEN};
+
+pub type rs_result = c_int;
+
+pub const RS_DONE: rs_result = 0;
+pub const RS_BLOCKED: rs_result = 1;
+pub const RS_RUNNING: rs_result = 2;
+pub const RS_TEST_SKIPPED: rs_result = 77;
+pub const RS_IO_ERROR: rs_result = 100;
+pub const RS_SYNTAX_ERROR: rs_result = 101;
+pub const RS_MEM_ERROR: rs_result = 102;
+pub const RS_INPUT_ENDED: rs_result = 103;
+pub const RS_BAD_MAGIC: rs_result = 104;
+pub const RS_UNIMPLEMENTED: rs_result = 105;
+pub const RS_CORRUPT: rs_result = 106;
+pub const RS_INTERNAL_ERROR: rs_result = 107;
+pub const RS_PARAM_ERROR: rs_result = 108;
+
+pub type rs_magic_number = c_int;
+
+pub const RS_DELTA_MAGIC: rs_magic_number = 0x72730236;
+pub const RS_MD4_SIG_MAGIC: rs_magic_number = 0x72730136;
+pub const RS_BLAKE2_SIG_MAGIC: rs_magic_number = 0x72730137;
+pub const RS_RK_MD4_SIG_MAGIC: rs_magic_number = 0x72730146;
+pub const RS_RK_BLAKE2_SIG_MAGIC: rs_magic_number = 0x72730147;
+
+pub type rs_long_t = i64;
+
+/// librsync's minimum strong sum length when the basis size is unknown.
+const RS_DEFAULT_MIN_STRONG_LEN: u32 = 12;
+
+#[repr(C)]
+pub struct rs_stats_t {
+    pub op: *const c_char,
+    pub lit_cmds: c_int,
+    pub lit_bytes: rs_long_t,
+    /// Not tracked; always 0.
+    pub lit_cmdbytes: rs_long_t,
+    pub copy_cmds: rs_long_t,
+    pub copy_bytes: rs_long_t,
+    /// Not tracked; always 0.
+    pub copy_cmdbytes: rs_long_t,
+    pub sig_cmds: rs_long_t,
+    pub sig_bytes: rs_long_t,
+    pub false_matches: c_int,
+    pub sig_blocks: rs_long_t,
+    pub block_len: usize,
+    pub in_bytes: rs_long_t,
+    pub out_bytes: rs_long_t,
+    pub start: i64,
+    pub end: i64,
+}
+
+#[repr(C)]
+pub struct rs_buffers_t {
+    pub next_in: *mut c_char,
+    pub avail_in: usize,
+    pub eof_in: c_int,
+    pub next_out: *mut c_char,
+    pub avail_out: usize,
+}
+
+/// A signature, indexed as it is read; `None` until a loadsig job is done.
+pub struct rs_signature_t(Option<Signature>);
+
+/// The signature behind `sig`, unless it is NULL or still loading.
+unsafe fn loaded<'a>(sig: *const rs_signature_t) -> Option<&'a Signature> {
+    sig.as_ref().and_then(|sig| sig.0.as_ref())
+}
+
+pub type rs_copy_cb = unsafe extern "C" fn(
+    opaque: *mut c_void,
+    pos: rs_long_t,
+    len: *mut usize,
+    buf: *mut *mut c_void,
+) -> rs_result;
+
+enum JobKind {
+    Job(Job<'static>),
+    /// librsync loads signatures through a job too; this one only buffers the
+    /// input and loads it at the end.
+    LoadSig {
+        data: Vec<u8>,
+        sumset: *mut rs_signature_t,
+        loaded: bool,
+        start: Instant,
+        stats: Stats,
+    },
+}
+
+pub struct rs_job_t {
+    kind: JobKind,
+
+    op: &'static CStr,
+
+    block_len: usize,
+
+    /// What `rs_job_statistics` last returned a pointer to.
+    stats: rs_stats_t,
+}
+
+#[repr(C)]
+pub struct FILE {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    fn fread(ptr: *mut c_void, size: usize, n: usize, f: *mut FILE) -> usize;
+    fn fwrite(ptr: *const c_void, size: usize, n: usize, f: *mut FILE) -> usize;
+    fn fflush(f: *mut FILE) -> c_int;
+    fn ferror(f: *mut FILE) -> c_int;
+    fn fseeko(f: *mut FILE, offset: i64, whence: c_int) -> c_int;
+    fn ftello(f: *mut FILE) -> i64;
+}
+
+/// A stdio stream owned by the caller.
+struct CFile(*mut FILE);
+
+impl Read for CFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { fread(buf.as_mut_ptr().cast(), 1, buf.len(), self.0) };
+        if n == 0 && unsafe { ferror(self.0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n)
+    }
+}
+
+impl Write for CFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = unsafe { fwrite(buf.as_ptr().cast(), 1, buf.len(), self.0) };
+        if n < buf.len() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if unsafe { fflush(self.0) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Seek for CFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(o) => (o as i64, 0),
+            SeekFrom::Current(o) => (o, 1),
+            SeekFrom::End(o) => (o, 2),
+        };
+        if unsafe { fseeko(self.0, offset, whence) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { ftello(self.0) } as u64)
+    }
+}
+
+/// A result other than `RS_DONE` from an `rs_copy_cb`, passed through `io::Error`
+/// so that `rs_job_iter` can return it.
+#[derive(Debug)]
+struct CopyFailed(rs_result);
+
+impl fmt::Display for CopyFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "copy callback failed with {}", self.0)
+    }
+}
+
+impl std::error::Error for CopyFailed {}
+
+/// The basis of a patch job, read through the caller's `rs_copy_cb`.
+struct CopyCb {
+    cb: rs_copy_cb,
+
+    arg: *mut c_void,
+
+    pos: u64,
+}
+
+impl Read for CopyCb {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut len = buf.len();
+        let mut p = buf.as_mut_ptr().cast::<c_void>();
+        match unsafe { (self.cb)(self.arg, self.pos as rs_long_t, &mut len, &mut p) } {
+            RS_DONE => {}
+            RS_INPUT_ENDED => return Ok(0),
+            r => return Err(io::Error::other(CopyFailed(r))),
+        }
+        // The callback may hand back a buffer of its own instead of filling ours.
+        let len = len.min(buf.len());
+        if p.cast::<u8>() != buf.as_mut_ptr() {
+            unsafe { ptr::copy_nonoverlapping(p.cast::<u8>(), buf.as_mut_ptr(), len) };
+        }
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for CopyCb {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(o) => o,
+            SeekFrom::Current(o) => self.pos.saturating_add_signed(o),
+            SeekFrom::End(_) => return Err(io::ErrorKind::Unsupported.into()),
+        };
+        Ok(self.pos)
+    }
+}
+
+fn result_of(e: &Error) -> rs_result {
+    match e {
+        Error::Io(e) => e
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<CopyFailed>())
+            .map_or(RS_IO_ERROR, |c| c.0),
+        Error::BadMagic(_) => RS_BAD_MAGIC,
+        Error::InvalidBlockLen(_) | Error::InvalidStrongLen(_) | Error::InvalidKey => {
+            RS_PARAM_ERROR
+        }
+        Error::CorruptSignature(_) | Error::CorruptDelta(_) => RS_CORRUPT,
+        Error::Cancelled => RS_IO_ERROR,
+    }
+}
+
+fn c_stats(op: &'static CStr, stats: &Stats, block_len: usize) -> rs_stats_t {
+    let end = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let signature = op == c"signature";
+    rs_stats_t {
+        op: op.as_ptr(),
+        lit_cmds: stats.lit_cmds as c_int,
+        lit_bytes: stats.lit_bytes as rs_long_t,
+        lit_cmdbytes: 0,
+        copy_cmds: stats.copy_cmds as rs_long_t,
+        copy_bytes: stats.copy_bytes as rs_long_t,
+        copy_cmdbytes: 0,
+        sig_cmds: if signature {
+            stats.blocks as rs_long_t
+        } else {
+            0
+        },
+        sig_bytes: if signature {
+            stats.in_bytes as rs_long_t
+        } else {
+            0
+        },
+        false_matches: stats.false_matches as c_int,
+        sig_blocks: stats.blocks as rs_long_t,
+        block_len,
+        in_bytes: stats.in_bytes as rs_long_t,
+        out_bytes: stats.out_bytes as rs_long_t,
+        start: end - stats.elapsed.as_secs() as i64,
+        end,
+    }
+}
+
+/// Stores the statistics of a finished whole-file operation, if asked for them.
+unsafe fn finish(
+    op: &'static CStr,
+    block_len: usize,
+    result: rdiff::Result<Stats>,
+    stats: *mut rs_stats_t,
+) -> rs_result {
+    match result {
+        Ok(s) => {
+            if !stats.is_null() {
+                *stats = c_stats(op, &s, block_len);
+            }
+            RS_DONE
+        }
+        Err(e) => result_of(&e),
+    }
+}
+
+fn format_of(magic: rs_magic_number) -> Option<SignatureFormat> {
+    SignatureFormat::from_magic(magic as u32).filter(|f| f.is_librsync())
+}
+
+/// Fills in a magic of 0, a block length of 0 and a strong sum length of 0 or -1
+/// with recommended values, for a basis of `old_fsize` bytes or, if negative, of
+/// unknown size.
+#[no_mangle]
+pub unsafe extern "C" fn rs_sig_args(
+    old_fsize: rs_long_t,
+    magic: *mut rs_magic_number,
+    block_len: *mut usize,
+    strong_len: *mut usize,
+) -> rs_result {
+    if *magic == 0 {
+        *magic = RS_RK_BLAKE2_SIG_MAGIC;
+    }
+    let Some(format) = format_of(*magic) else {
+        return RS_BAD_MAGIC;
+    };
+    let recommended = match u64::try_from(old_fsize) {
+        Ok(l) => SignatureOptions::recommended(l, format),
+        Err(_) => SignatureOptions {
+            magic: format,
+            block_len: DEFAULT_BLOCK_LEN,
+            strong_len: RS_DEFAULT_MIN_STRONG_LEN.min(format.max_strong_len()),
+            key: None,
+        },
+    };
+    let max_strong_len = format.max_strong_len() as usize;
+    if *block_len == 0 {
+        *block_len = recommended.block_len as usize;
+    }
+    if *strong_len == 0 {
+        *strong_len = max_strong_len;
+    } else if *strong_len == usize::MAX {
+        *strong_len = match u64::try_from(old_fsize) {
+            Ok(l) => {
+                let block_len = u32::try_from(*block_len).unwrap_or(u32::MAX);
+                (min_strong_len(l, block_len) as usize).min(max_strong_len)
+            }
+            Err(_) => recommended.strong_len as usize,
+        };
+    } else if *strong_len > max_strong_len {
+        return RS_PARAM_ERROR;
+    }
+    RS_DONE
+}
+
+unsafe fn sig_options(
+    old_fsize: rs_long_t,
+    mut magic: rs_magic_number,
+    mut block_len: usize,
+    mut strong_len: usize,
+) -> Result<SignatureOptions, rs_result> {
+    match rs_sig_args(old_fsize, &mut magic, &mut block_len, &mut strong_len) {
+        RS_DONE => {}
+        r => return Err(r),
+    }
+    let options = SignatureOptions {
+        magic: format_of(magic).ok_or(RS_BAD_MAGIC)?,
+        block_len: u32::try_from(block_len).map_err(|_| RS_PARAM_ERROR)?,
+        strong_len: strong_len as u32,
+        key: None,
+    };
+    options.validate().map_err(|e| result_of(&e))?;
+    Ok(options)
+}
+
+/// Size of the file behind `f`, or -1 if it cannot seek, like librsync's
+/// `rs_file_size`.
+fn file_size(f: &mut CFile) -> rs_long_t {
+    let Ok(pos) = f.stream_position() else {
+        return -1;
+    };
+    match (f.seek(SeekFrom::End(0)), f.seek(SeekFrom::Start(pos))) {
+        (Ok(size), Ok(_)) => size as rs_long_t,
+        _ => -1,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_sig_file(
+    old_file: *mut FILE,
+    sig_file: *mut FILE,
+    block_len: usize,
+    strong_len: usize,
+    sig_magic: rs_magic_number,
+    stats: *mut rs_stats_t,
+) -> rs_result {
+    let old_file = &mut CFile(old_file);
+    let options = match sig_options(file_size(old_file), sig_magic, block_len, strong_len) {
+        Ok(options) => options,
+        Err(r) => return r,
+    };
+    let result = generate_signature(old_file, &options, &mut CFile(sig_file));
+    finish(c"signature", options.block_len as usize, result, stats)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_loadsig_file(
+    sig_file: *mut FILE,
+    sumset: *mut *mut rs_signature_t,
+    stats: *mut rs_stats_t,
+) -> rs_result {
+    let start = Instant::now();
+    let sig = match Signature::load(&mut CFile(sig_file)) {
+        Ok(sig) => sig,
+        Err(e) => return result_of(&e),
+    };
+    let s = Stats {
+        in_bytes: signature_len(&sig),
+        blocks: sig.len() as u64,
+        elapsed: start.elapsed(),
+        ..Stats::default()
+    };
+    let block_len = sig.block_len() as usize;
+    *sumset = Box::into_raw(Box::new(rs_signature_t(Some(sig))));
+    finish(c"loadsig", block_len, Ok(s), stats)
+}
+
+/// Length of `sig` as stored.
+fn signature_len(sig: &Signature) -> u64 {
+    let key_len = sig.key().map_or(0, |k| k.len());
+    (12 + key_len + sig.len() * (4 + sig.strong_len() as usize)) as u64
+}
+
+/// The index is built as a signature is loaded, so there is nothing left to do.
+#[no_mangle]
+pub unsafe extern "C" fn rs_build_hash_table(sums: *mut rs_signature_t) -> rs_result {
+    if sums.is_null() {
+        return RS_PARAM_ERROR;
+    }
+    RS_DONE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_free_sumset(psums: *mut rs_signature_t) {
+    if !psums.is_null() {
+        drop(Box::from_raw(psums));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_sumset_dump(sums: *const rs_signature_t) {
+    if let Some(sig) = loaded(sums) {
+        let _ = sig.dump(&mut io::stderr(), true);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_delta_file(
+    sig: *mut rs_signature_t,
+    new_file: *mut FILE,
+    delta_file: *mut FILE,
+    stats: *mut rs_stats_t,
+) -> rs_result {
+    let Some(sig) = loaded(sig) else {
+        return RS_PARAM_ERROR;
+    };
+    let result = generate_delta_from_signature(sig, &mut CFile(new_file), &mut CFile(delta_file));
+    finish(c"delta", sig.block_len() as usize, result, stats)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_patch_file(
+    basis_file: *mut FILE,
+    delta_file: *mut FILE,
+    new_file: *mut FILE,
+    stats: *mut rs_stats_t,
+) -> rs_result {
+    let result = apply_patch(
+        &mut CFile(basis_file),
+        &mut CFile(delta_file),
+        &mut CFile(new_file),
+    );
+    finish(c"patch", 0, result, stats)
+}
+
+/// An `rs_copy_cb` reading the basis from the `FILE *` in `arg`.
+#[no_mangle]
+pub unsafe extern "C" fn rs_file_copy_cb(
+    arg: *mut c_void,
+    pos: rs_long_t,
+    len: *mut usize,
+    buf: *mut *mut c_void,
+) -> rs_result {
+    let f = arg.cast::<FILE>();
+    if fseeko(f, pos, 0) != 0 {
+        return RS_IO_ERROR;
+    }
+    let got = fread(*buf, 1, *len, f);
+    if got == 0 {
+        return if ferror(f) != 0 {
+            RS_IO_ERROR
+        } else {
+            RS_INPUT_ENDED
+        };
+    }
+    *len = got;
+    RS_DONE
+}
+
+fn new_job(kind: JobKind, op: &'static CStr, block_len: usize) -> *mut rs_job_t {
+    Box::into_raw(Box::new(rs_job_t {
+        kind,
+        op,
+        block_len,
+        stats: c_stats(op, &Stats::default(), block_len),
+    }))
+}
+
+/// Returns NULL if the arguments are invalid.
+#[no_mangle]
+pub unsafe extern "C" fn rs_sig_begin(
+    block_len: usize,
+    strong_len: usize,
+    sig_magic: rs_magic_number,
+) -> *mut rs_job_t {
+    let Ok(options) = sig_options(-1, sig_magic, block_len, strong_len) else {
+        return ptr::null_mut();
+    };
+    match Job::signature(&options) {
+        Ok(job) => new_job(JobKind::Job(job), c"signature", options.block_len as usize),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// `sig` must outlive the job. Returns NULL if `sig` is NULL or still loading.
+#[no_mangle]
+pub unsafe extern "C" fn rs_delta_begin(sig: *mut rs_signature_t) -> *mut rs_job_t {
+    let Some(sig) = loaded(sig) else {
+        return ptr::null_mut();
+    };
+    new_job(
+        JobKind::Job(Job::delta(sig)),
+        c"delta",
+        sig.block_len() as usize,
+    )
+}
+
+/// Sets `*sumset` to a signature the job fills in when it is done. The caller
+/// frees it with `rs_free_sumset`, whether or not the job succeeds.
+#[no_mangle]
+pub unsafe extern "C" fn rs_loadsig_begin(sumset: *mut *mut rs_signature_t) -> *mut rs_job_t {
+    if sumset.is_null() {
+        return ptr::null_mut();
+    }
+    *sumset = Box::into_raw(Box::new(rs_signature_t(None)));
+    new_job(
+        JobKind::LoadSig {
+            data: Vec::new(),
+            sumset: *sumset,
+            loaded: false,
+            start: Instant::now(),
+            stats: Stats::default(),
+        },
+        c"loadsig",
+        0,
+    )
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_patch_begin(
+    copy_cb: Option<rs_copy_cb>,
+    copy_arg: *mut c_void,
+) -> *mut rs_job_t {
+    let Some(cb) = copy_cb else {
+        return ptr::null_mut();
+    };
+    let basis = CopyCb {
+        cb,
+        arg: copy_arg,
+        pos: 0,
+    };
+    new_job(JobKind::Job(Job::patch(basis)), c"patch", 0)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_job_iter(job: *mut rs_job_t, buffers: *mut rs_buffers_t) -> rs_result {
+    let job = &mut *job;
+    let b = &mut *buffers;
+    let next_in: &[u8] = if b.avail_in == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(b.next_in.cast(), b.avail_in)
+    };
+    let next_out: &mut [u8] = if b.avail_out == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(b.next_out.cast(), b.avail_out)
+    };
+
+    let (result, consumed, produced) = match &mut job.kind {
+        JobKind::Job(j) => {
+            let mut buffers = Buffers::new(next_in, b.eof_in != 0, next_out);
+            let result = match j.iter(&mut buffers) {
+                Ok(JobStatus::Done) => RS_DONE,
+                Ok(JobStatus::Blocked) => RS_BLOCKED,
+                Err(e) => result_of(&e),
+            };
+            let consumed = b.avail_in - buffers.next_in.len();
+            let produced = b.avail_out - buffers.next_out.len();
+            (result, consumed, produced)
+        }
+        JobKind::LoadSig {
+            data,
+            sumset,
+            loaded,
+            start,
+            stats,
+        } => {
+            data.extend_from_slice(next_in);
+            stats.in_bytes = data.len() as u64;
+            stats.elapsed = start.elapsed();
+            let result = if *loaded {
+                RS_DONE
+            } else if b.eof_in == 0 {
+                RS_BLOCKED
+            } else {
+                match Signature::load(&mut &data[..]) {
+                    Ok(sig) => {
+                        stats.blocks = sig.len() as u64;
+                        job.block_len = sig.block_len() as usize;
+                        (**sumset).0 = Some(sig);
+                        *loaded = true;
+                        RS_DONE
+                    }
+                    Err(e) => result_of(&e),
+                }
+            };
+            (result, b.avail_in, 0)
+        }
+    };
+    b.next_in = b.next_in.wrapping_add(consumed);
+    b.avail_in -= consumed;
+    b.next_out = b.next_out.wrapping_add(produced);
+    b.avail_out -= produced;
+    result
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_job_statistics(job: *mut rs_job_t) -> *const rs_stats_t {
+    let job = &mut *job;
+    let stats = match &job.kind {
+        JobKind::Job(j) => j.statistics(),
+        JobKind::LoadSig { stats, .. } => *stats,
+    };
+    job.stats = c_stats(job.op, &stats, job.block_len);
+    &job.stats
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rs_job_free(job: *mut rs_job_t) -> rs_result {
+    if !job.is_null() {
+        drop(Box::from_raw(job));
+    }
+    RS_DONE
+}
+
+#[no_mangle]
+pub extern "C" fn rs_strerror(r: rs_result) -> *const c_char {
+    let msg = match r {
+        RS_DONE => c"OK",
+        RS_RUNNING => c"still running",
+        RS_BLOCKED => c"blocked waiting for input or output buffers",
+        RS_BAD_MAGIC => c"bad magic number at start of stream",
+        RS_INPUT_ENDED => c"unexpected end of input",
+        RS_CORRUPT => c"stream corrupt",
+        RS_UNIMPLEMENTED => c"unimplemented case",
+        RS_MEM_ERROR => c"out of memory",
+        RS_IO_ERROR => c"IO error",
+        RS_SYNTAX_ERROR => c"bad command line syntax",
+        RS_INTERNAL_ERROR => c"library internal error",
+        RS_PARAM_ERROR => c"invalid parameter",
+        RS_TEST_SKIPPED => c"test skipped",
+        _ => c"unexplained problem",
+    };
+    msg.as_ptr()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sig_args(
+        old_fsize: rs_long_t,
+        magic: rs_magic_number,
+        block_len: usize,
+        strong_len: usize,
+    ) -> (rs_result, rs_magic_number, usize, usize) {
+        let (mut magic, mut block_len, mut strong_len) = (magic, block_len, strong_len);
+        let r = unsafe { rs_sig_args(old_fsize, &mut magic, &mut block_len, &mut strong_len) };
+        (r, magic, block_len, strong_len)
+    }
+
+    #[test]
+    pub fn recommended_args() {
+        assert_eq!(
+            sig_args(-1, 0, 0, 0),
+            (RS_DONE, RS_RK_BLAKE2_SIG_MAGIC, 2048, 32)
+        );
+        assert_eq!(
+            sig_args(-1, RS_MD4_SIG_MAGIC, 0, usize::MAX),
+            (RS_DONE, RS_MD4_SIG_MAGIC, 2048, 12)
+        );
+        assert_eq!(
+            sig_args(1 << 30, 0, 0, usize::MAX),
+            (RS_DONE, RS_RK_BLAKE2_SIG_MAGIC, 32768, 8)
+        );
+        // An explicit block length sets the strong sum length too.
+        assert_eq!(
+            sig_args(1 << 30, 0, 1024, usize::MAX),
+            (RS_DONE, RS_RK_BLAKE2_SIG_MAGIC, 1024, 9)
+        );
+        assert_eq!(
+            sig_args(-1, RS_MD4_SIG_MAGIC, 1024, 8),
+            (RS_DONE, RS_MD4_SIG_MAGIC, 1024, 8)
+        );
+        assert_eq!(sig_args(-1, RS_MD4_SIG_MAGIC, 0, 17).0, RS_PARAM_ERROR);
+        assert_eq!(sig_args(-1, RS_DELTA_MAGIC, 0, 0).0, RS_BAD_MAGIC);
+        // The keyed formats need a key, which librsync's API has no way to pass.
+        assert_eq!(sig_args(-1, 0x72730138, 0, 0).0, RS_BAD_MAGIC);
+    }
+
+    #[test]
+    pub fn result_codes() {
+        assert_eq!(result_of(&Error::BadMagic(0)), RS_BAD_MAGIC);
+        assert_eq!(result_of(&Error::CorruptDelta("x")), RS_CORRUPT);
+        assert_eq!(result_of(&Error::InvalidStrongLen(40)), RS_PARAM_ERROR);
+        assert_eq!(
+            result_of(&Error::Io(io::Error::other("disk on fire"))),
+            RS_IO_ERROR
+        );
+        assert_eq!(
+            result_of(&Error::Io(io::Error::other(CopyFailed(RS_MEM_ERROR)))),
+            RS_MEM_ERROR
+        );
+        let msg = unsafe { CStr::from_ptr(rs_strerror(RS_CORRUPT)) };
+        assert_eq!(msg, c"stream corrupt");
+    }
+}
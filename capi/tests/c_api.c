@@ -0,0 +1,231 @@
+/* Drives the library through librsync.h, the way a librsync user would. */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "librsync.h"
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #cond);                                                  \
+            exit(1);                                                         \
+        }                                                                    \
+    } while (0)
+
+#define BASIS_LEN 100000
+#define INSERT_AT 40000
+#define INSERT_LEN 300
+#define NEW_LEN (BASIS_LEN + INSERT_LEN)
+#define CAP (2 * NEW_LEN)
+
+static char basis[BASIS_LEN], new_file[NEW_LEN];
+
+static void pseudo_random(char *buf, size_t len, unsigned seed)
+{
+    unsigned x = seed;
+    for (size_t i = 0; i < len; i++) {
+        x = x * 1103515245 + 12345;
+        buf[i] = (char)(x >> 16);
+    }
+}
+
+static FILE *file_with(const char *data, size_t len)
+{
+    FILE *f = tmpfile();
+    CHECK(f != NULL);
+    CHECK(fwrite(data, 1, len, f) == len);
+    rewind(f);
+    return f;
+}
+
+static size_t contents(FILE *f, char *buf)
+{
+    rewind(f);
+    return fread(buf, 1, CAP, f);
+}
+
+/* Runs job over in, through input and output windows of at most chunk bytes. */
+static rs_result run_job(rs_job_t *job, const char *in, size_t in_len,
+                         char *out, size_t *out_len, size_t chunk)
+{
+    rs_buffers_t buf;
+    size_t fed = 0, got = 0;
+    rs_result r;
+    do {
+        size_t n = in_len - fed < chunk ? in_len - fed : chunk;
+        buf.next_in = (char *)in + fed;
+        buf.avail_in = n;
+        buf.eof_in = fed + n == in_len;
+        buf.next_out = out + got;
+        buf.avail_out = CAP - got < chunk ? CAP - got : chunk;
+        r = rs_job_iter(job, &buf);
+        fed += n - buf.avail_in;
+        got = buf.next_out - out;
+    } while (r == RS_BLOCKED);
+    *out_len = got;
+    return r;
+}
+
+/* An rs_copy_cb over a buffer in memory, handing out pointers into it. */
+static rs_result memory_copy_cb(void *arg, rs_long_t pos, size_t *len,
+                                void **buf)
+{
+    (void)arg;
+    if (pos >= BASIS_LEN)
+        return RS_INPUT_ENDED;
+    if (*len > (size_t)(BASIS_LEN - pos))
+        *len = BASIS_LEN - pos;
+    *buf = basis + pos;
+    return RS_DONE;
+}
+
+static void whole_files(void)
+{
+    static char out[CAP];
+    rs_stats_t stats;
+    rs_signature_t *sig;
+    FILE *basis_f = file_with(basis, BASIS_LEN);
+    FILE *new_f = file_with(new_file, NEW_LEN);
+    FILE *sig_f = tmpfile(), *delta_f = tmpfile(), *out_f = tmpfile();
+
+    CHECK(rs_sig_file(basis_f, sig_f, 1024, 0, RS_RK_BLAKE2_SIG_MAGIC,
+                      &stats) == RS_DONE);
+    CHECK(strcmp(stats.op, "signature") == 0);
+    CHECK(stats.in_bytes == BASIS_LEN);
+    CHECK(stats.sig_blocks == (BASIS_LEN + 1023) / 1024);
+
+    rewind(sig_f);
+    CHECK(rs_loadsig_file(sig_f, &sig, &stats) == RS_DONE);
+    CHECK(stats.sig_blocks == (BASIS_LEN + 1023) / 1024);
+    CHECK(rs_build_hash_table(sig) == RS_DONE);
+
+    CHECK(rs_delta_file(sig, new_f, delta_f, &stats) == RS_DONE);
+    /* The block the bytes went into is sent as literals too. */
+    CHECK(stats.lit_bytes >= INSERT_LEN && stats.lit_bytes <= INSERT_LEN + 1024);
+    CHECK(stats.lit_bytes + stats.copy_bytes == NEW_LEN);
+    CHECK(stats.out_bytes < 2000);
+    rs_free_sumset(sig);
+
+    rewind(basis_f);
+    rewind(delta_f);
+    CHECK(rs_patch_file(basis_f, delta_f, out_f, NULL) == RS_DONE);
+    CHECK(contents(out_f, out) == NEW_LEN);
+    CHECK(memcmp(out, new_file, NEW_LEN) == 0);
+
+    /* A signature is not a delta. */
+    rewind(sig_f);
+    CHECK(rs_patch_file(basis_f, sig_f, out_f, NULL) == RS_BAD_MAGIC);
+    CHECK(strcmp(rs_strerror(RS_BAD_MAGIC),
+                 "bad magic number at start of stream") == 0);
+
+    fclose(basis_f);
+    fclose(new_f);
+    fclose(sig_f);
+    fclose(delta_f);
+    fclose(out_f);
+}
+
+/* rs_sig_file picks the block and strong sum lengths for the basis's size. */
+static void default_sig_args(void)
+{
+    static char sig_buf[CAP];
+    rs_magic_number magic = 0;
+    size_t block_len = 0, strong_len = (size_t)-1;
+    size_t unknown_block_len = 0, unknown_strong_len = (size_t)-1;
+    rs_stats_t stats;
+    FILE *basis_f = file_with(basis, BASIS_LEN);
+    FILE *sig_f = tmpfile();
+
+    CHECK(rs_sig_args(BASIS_LEN, &magic, &block_len, &strong_len) == RS_DONE);
+    CHECK(rs_sig_args(-1, &magic, &unknown_block_len, &unknown_strong_len) ==
+          RS_DONE);
+    CHECK(block_len != unknown_block_len);
+
+    CHECK(rs_sig_file(basis_f, sig_f, 0, (size_t)-1, 0, &stats) == RS_DONE);
+    CHECK(stats.block_len == block_len);
+    CHECK(stats.sig_blocks == (rs_long_t)((BASIS_LEN + block_len - 1) / block_len));
+    CHECK(contents(sig_f, sig_buf) ==
+          12 + (size_t)stats.sig_blocks * (4 + strong_len));
+
+    fclose(basis_f);
+    fclose(sig_f);
+}
+
+static void jobs(void)
+{
+    static char sig_buf[CAP], delta[CAP], out[CAP];
+    size_t sig_len, delta_len, out_len, ignored;
+    rs_signature_t *sig, *bad_sig;
+    rs_job_t *job;
+    FILE *basis_f = file_with(basis, BASIS_LEN);
+
+    job = rs_sig_begin(512, 8, RS_MD4_SIG_MAGIC);
+    CHECK(job != NULL);
+    CHECK(run_job(job, basis, BASIS_LEN, sig_buf, &sig_len, 100) == RS_DONE);
+    CHECK(sig_len == 12 + (BASIS_LEN + 511) / 512 * 12);
+    CHECK(rs_job_statistics(job)->in_bytes == BASIS_LEN);
+    rs_job_free(job);
+    CHECK(rs_sig_begin(512, 17, RS_MD4_SIG_MAGIC) == NULL);
+
+    /* The signature is handed out at once, but cannot be used until loaded. */
+    job = rs_loadsig_begin(&sig);
+    CHECK(sig != NULL);
+    CHECK(rs_delta_begin(sig) == NULL);
+    CHECK(run_job(job, sig_buf, sig_len, out, &ignored, 33) == RS_DONE);
+    CHECK(rs_job_statistics(job)->sig_blocks == (BASIS_LEN + 511) / 512);
+    rs_job_free(job);
+    CHECK(rs_build_hash_table(sig) == RS_DONE);
+    CHECK(rs_delta_begin(NULL) == NULL);
+    rs_sumset_dump(NULL);
+
+    /* A failed load still hands out a signature to free. */
+    job = rs_loadsig_begin(&bad_sig);
+    CHECK(run_job(job, new_file, NEW_LEN, out, &ignored, 100) ==
+          RS_BAD_MAGIC);
+    rs_job_free(job);
+    rs_free_sumset(bad_sig);
+
+    job = rs_delta_begin(sig);
+    CHECK(run_job(job, new_file, NEW_LEN, delta, &delta_len, 4096) ==
+          RS_DONE);
+    CHECK(rs_job_statistics(job)->lit_bytes <= INSERT_LEN + 512);
+    rs_job_free(job);
+    rs_free_sumset(sig);
+
+    job = rs_patch_begin(rs_file_copy_cb, basis_f);
+    CHECK(run_job(job, delta, delta_len, out, &out_len, 1000) == RS_DONE);
+    CHECK(out_len == NEW_LEN);
+    CHECK(memcmp(out, new_file, NEW_LEN) == 0);
+    CHECK(rs_job_statistics(job)->out_bytes == NEW_LEN);
+    rs_job_free(job);
+
+    job = rs_patch_begin(memory_copy_cb, NULL);
+    CHECK(run_job(job, delta, delta_len, out, &out_len, 7) == RS_DONE);
+    CHECK(out_len == NEW_LEN);
+    CHECK(memcmp(out, new_file, NEW_LEN) == 0);
+    rs_job_free(job);
+
+    /* The truncated delta is missing its end command. */
+    job = rs_patch_begin(memory_copy_cb, NULL);
+    CHECK(run_job(job, delta, delta_len - 1, out, &out_len, 1000) ==
+          RS_CORRUPT);
+    rs_job_free(job);
+
+    fclose(basis_f);
+}
+
+int main(void)
+{
+    pseudo_random(basis, BASIS_LEN, 1);
+    memcpy(new_file, basis, INSERT_AT);
+    pseudo_random(new_file + INSERT_AT, INSERT_LEN, 2);
+    memcpy(new_file + INSERT_AT + INSERT_LEN, basis + INSERT_AT,
+           BASIS_LEN - INSERT_AT);
+
+    whole_files();
+    default_sig_args();
+    jobs();
+    return 0;
+}
@@ -0,0 +1,34 @@
+#![cfg(unix)]
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Compiles `c_api.c` with the system C compiler against the cdylib and runs it.
+#[test]
+pub fn c_program() {
+    // Integration tests are built into target/<profile>/deps, next to the cdylib.
+    let exe = env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    let program = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("c_api");
+
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-Iinclude"])
+        .arg("tests/c_api.c")
+        .arg("-L")
+        .arg(lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .args(["-lrsync", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success(), "compiling c_api.c failed");
+
+    // cargo test puts target/<profile> on the library path ahead of the rpath,
+    // and the librsync.so there is only refreshed by cargo build.
+    let status = Command::new(&program)
+        .env("LD_LIBRARY_PATH", lib_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "c_api failed");
+}
@@ -0,0 +1,44 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use rdiff::job::{Buffers, Job, JobStatus};
+use rdiff::patch::apply_patch;
+
+/// Runs a patch job over `delta` a few bytes at a time.
+fn patch_job(basis: &[u8], delta: &[u8]) -> rdiff::Result<Vec<u8>> {
+    let mut job = Job::patch(Cursor::new(basis));
+    let mut result = Vec::new();
+    let mut out = [0u8; 100];
+    let mut fed = 0;
+    loop {
+        let end = (fed + 7).min(delta.len());
+        let mut buffers = Buffers::new(&delta[fed..end], end == delta.len(), &mut out);
+        let status = job.iter(&mut buffers)?;
+        fed = end - buffers.next_in.len();
+        let produced = 100 - buffers.next_out.len();
+        result.extend_from_slice(&out[..produced]);
+        if status == JobStatus::Done {
+            return Ok(result);
+        }
+    }
+}
+
+// The first byte says how much of the rest is the basis; the remainder is the delta.
+fuzz_target!(|data: &[u8]| {
+    let Some((&n, rest)) = data.split_first() else {
+        return;
+    };
+    let (basis, delta) = rest.split_at((n as usize).min(rest.len()));
+
+    let mut out = Vec::new();
+    let streamed = apply_patch(&mut Cursor::new(basis), &mut &delta[..], &mut out).map(|_| out);
+    let pushed = patch_job(basis, delta);
+    // Both drivers must accept the same deltas and produce the same files.
+    match (streamed, pushed) {
+        (Ok(a), Ok(b)) => assert_eq!(a, b),
+        (Err(_), Err(_)) => {}
+        (a, b) => panic!("apply_patch gave {:?} but the job gave {:?}", a, b),
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rdiff::delta::{Command, Commands};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(commands) = Commands::new(data) {
+        let mut ended = false;
+        for command in commands {
+            match command {
+                Ok(c) => {
+                    assert!(!ended, "command after the end command");
+                    ended = c == Command::End;
+                    let _ = c.to_string();
+                }
+                Err(_) => assert!(!ended),
+            }
+        }
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rdiff::sumset::Signature;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(sig) = Signature::load(&mut &data[..]) {
+        for block in 0..sig.len() {
+            let weak = sig.weak_sum(block);
+            assert!(sig.index().candidates(weak).contains(&block));
+            assert_eq!(sig.strong_sum(block).len(), sig.strong_len() as usize);
+        }
+        sig.dump(&mut std::io::sink(), true).unwrap();
+    }
+});
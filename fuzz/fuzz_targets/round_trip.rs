@@ -0,0 +1,43 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use rdiff::delta::generate_delta;
+use rdiff::mksum::{generate_signature, SignatureFormat, SignatureOptions};
+use rdiff::patch::apply_patch;
+
+const FORMATS: [SignatureFormat; 4] = [
+    SignatureFormat::Md4Sig,
+    SignatureFormat::Blake2Sig,
+    SignatureFormat::RkMd4Sig,
+    SignatureFormat::RkBlake2Sig,
+];
+
+// Four bytes of options, then the basis and new file split at a point the
+// fourth byte picks.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 4 {
+        return;
+    }
+    let magic = FORMATS[data[0] as usize % FORMATS.len()];
+    let block_len = 1 + data[1] as u32 % 64;
+    // Short strong sums let blocks collide for real, which is not a bug.
+    let strong_len = 8 + data[2] as u32 % (magic.max_strong_len() - 7);
+    let rest = &data[4..];
+    let (basis, new) = rest.split_at(data[3] as usize * rest.len() / 256);
+
+    let options = SignatureOptions {
+        magic,
+        block_len,
+        strong_len,
+        key: None,
+    };
+    let mut sig = Vec::new();
+    generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+    let mut delta = Vec::new();
+    generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+    let mut out = Vec::new();
+    apply_patch(&mut Cursor::new(basis), &mut &delta[..], &mut out).unwrap();
+    assert_eq!(out, new);
+});
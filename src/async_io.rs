@@ -0,0 +1,455 @@
+use std::io::{self, SeekFrom};
+use std::time::Instant;
+
+use cast::usize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use crate::delta::{command_len, scanner, Command, DELTA_MAGIC, INPUT_CHUNK_LEN};
+use crate::error::{Error, Result};
+use crate::mksum::{write_header, SignatureOptions};
+use crate::progress::Monitor;
+use crate::stats::Stats;
+use crate::sumset::Signature;
+
+async fn fill_buffer<R>(inf: &mut R, buf: &mut [u8]) -> io::Result<usize>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut bytes_read: usize = 0;
+    while bytes_read < buf.len() {
+        let l = inf.read(&mut buf[bytes_read..]).await?;
+        if l == 0 {
+            break;
+        }
+        bytes_read += l;
+    }
+    Ok(bytes_read)
+}
+
+/// `mksum::generate_signature` over tokio streams.
+pub async fn generate_signature<R, W>(
+    basis: &mut R,
+    options: &SignatureOptions,
+    sig: &mut W,
+) -> Result<Stats>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    generate_signature_monitored(basis, options, sig, &mut Monitor::new()).await
+}
+
+/// Like `generate_signature`, reporting to and checking `monitor` after each block.
+pub async fn generate_signature_monitored<R, W>(
+    basis: &mut R,
+    options: &SignatureOptions,
+    sig: &mut W,
+    monitor: &mut Monitor<'_>,
+) -> Result<Stats>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    options.validate()?;
+    let block_sums = options.block_sums();
+    let mut buf = vec![0; usize(options.block_len)];
+    let mut out = Vec::new();
+    write_header(options, &mut out)?;
+
+    loop {
+        let l = fill_buffer(basis, &mut buf).await?;
+        if l > 0 {
+            stats.in_bytes += l as u64;
+            stats.blocks += 1;
+            block_sums(&buf[..l], &mut out)?;
+        }
+        if l < buf.len() || out.len() >= INPUT_CHUNK_LEN {
+            sig.write_all(&out).await?;
+            stats.out_bytes += out.len() as u64;
+            out.clear();
+        }
+        if l > 0 {
+            stats.elapsed = start.elapsed();
+            monitor.check(&stats)?;
+        }
+        if l < buf.len() {
+            break;
+        }
+    }
+    sig.flush().await?;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+/// `delta::generate_delta` over tokio streams.
+pub async fn generate_delta<S, R, W>(sig: &mut S, new_file: &mut R, delta: &mut W) -> Result<Stats>
+where
+    S: AsyncRead + Unpin + ?Sized,
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    // Signatures are small next to the files they describe, so read it whole
+    // and parse it with the blocking loader.
+    let mut buf = Vec::new();
+    sig.read_to_end(&mut buf).await?;
+    let sig = Signature::load(&mut &buf[..])?;
+    generate_delta_from_signature(&sig, new_file, delta).await
+}
+
+/// Like `generate_delta`, reporting to and checking `monitor` after each chunk
+/// of the new file.
+pub async fn generate_delta_monitored<S, R, W>(
+    sig: &mut S,
+    new_file: &mut R,
+    delta: &mut W,
+    monitor: &mut Monitor<'_>,
+) -> Result<Stats>
+where
+    S: AsyncRead + Unpin + ?Sized,
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut buf = Vec::new();
+    sig.read_to_end(&mut buf).await?;
+    let sig = Signature::load(&mut &buf[..])?;
+    generate_delta_from_signature_monitored(&sig, new_file, delta, monitor).await
+}
+
+/// `delta::generate_delta_from_signature` over tokio streams.
+pub async fn generate_delta_from_signature<R, W>(
+    sig: &Signature,
+    new_file: &mut R,
+    delta: &mut W,
+) -> Result<Stats>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    generate_delta_from_signature_monitored(sig, new_file, delta, &mut Monitor::new()).await
+}
+
+/// Like `generate_delta_from_signature`, reporting to and checking `monitor`
+/// after each chunk of the new file.
+pub async fn generate_delta_from_signature_monitored<R, W>(
+    sig: &Signature,
+    new_file: &mut R,
+    delta: &mut W,
+    monitor: &mut Monitor<'_>,
+) -> Result<Stats>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let start = Instant::now();
+    let mut out_bytes = 0;
+    let mut scanner = scanner(sig);
+    let mut buf = vec![0; INPUT_CHUNK_LEN];
+    let mut out = DELTA_MAGIC.to_be_bytes().to_vec();
+    loop {
+        let l = fill_buffer(new_file, &mut buf).await?;
+        let eof = l < buf.len();
+        scanner.scan(&buf[..l], eof, &mut out)?;
+        if eof {
+            Command::End.write_to(&mut out)?;
+        }
+        delta.write_all(&out).await?;
+        out_bytes += out.len() as u64;
+        out.clear();
+        monitor.check(&Stats {
+            out_bytes,
+            elapsed: start.elapsed(),
+            ..*scanner.stats()
+        })?;
+        if eof {
+            break;
+        }
+    }
+    delta.flush().await?;
+    Ok(Stats {
+        out_bytes,
+        elapsed: start.elapsed(),
+        ..*scanner.stats()
+    })
+}
+
+async fn copy_exact<R, W>(from: &mut R, len: u64, out: &mut W, short: Error) -> Result<()>
+where
+    R: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let copied = tokio::io::copy(&mut from.take(len), out).await?;
+    if copied < len {
+        return Err(short);
+    }
+    Ok(())
+}
+
+/// `patch::apply_patch` over tokio streams.
+pub async fn apply_patch<B, D, W>(basis: &mut B, delta: &mut D, out: &mut W) -> Result<Stats>
+where
+    B: AsyncRead + AsyncSeek + Unpin + ?Sized,
+    D: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    apply_patch_monitored(basis, delta, out, &mut Monitor::new()).await
+}
+
+/// Like `apply_patch`, reporting to and checking `monitor` after each command.
+pub async fn apply_patch_monitored<B, D, W>(
+    basis: &mut B,
+    delta: &mut D,
+    out: &mut W,
+    monitor: &mut Monitor<'_>,
+) -> Result<Stats>
+where
+    B: AsyncRead + AsyncSeek + Unpin + ?Sized,
+    D: AsyncRead + Unpin + ?Sized,
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    let delta = &mut tokio::io::BufReader::new(delta);
+
+    let mut magic = [0u8; 4];
+    if fill_buffer(delta, &mut magic).await? < magic.len() {
+        return Err(Error::CorruptDelta("truncated header"));
+    }
+    let magic = u32::from_be_bytes(magic);
+    if magic != DELTA_MAGIC {
+        return Err(Error::BadMagic(magic));
+    }
+    stats.in_bytes = 4;
+
+    let mut cmd = [0u8; 17];
+    loop {
+        if fill_buffer(delta, &mut cmd[..1]).await? == 0 {
+            return Err(Error::CorruptDelta("missing end command"));
+        }
+        let l = command_len(cmd[0]);
+        if 1 + fill_buffer(delta, &mut cmd[1..l]).await? < l {
+            return Err(Error::CorruptDelta("truncated command"));
+        }
+        stats.in_bytes += l as u64;
+        let command = Command::read_from(&mut &cmd[..l])?;
+        stats.count_command(&command);
+        match command {
+            Command::End => break,
+            Command::Literal { len } => {
+                copy_exact(
+                    delta,
+                    len,
+                    out,
+                    Error::CorruptDelta("truncated literal data"),
+                )
+                .await?;
+                stats.in_bytes += len;
+            }
+            Command::Copy { offset, len } => {
+                basis.seek(SeekFrom::Start(offset)).await?;
+                copy_exact(
+                    basis,
+                    len,
+                    out,
+                    Error::CorruptDelta("copy past the end of the basis"),
+                )
+                .await?;
+            }
+        }
+        stats.out_bytes = stats.lit_bytes + stats.copy_bytes;
+        stats.elapsed = start.elapsed();
+        monitor.check(&stats)?;
+    }
+    out.flush().await?;
+    stats.out_bytes = stats.lit_bytes + stats.copy_bytes;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mksum::SignatureFormat;
+    use crate::progress::CancelToken;
+    use crate::testutil::{pseudo_random, without_elapsed};
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn matches_blocking_functions() {
+        let basis = pseudo_random(200_000, 1);
+        let mut new = basis.clone();
+        new.splice(100_000..100_000, pseudo_random(5000, 2));
+        new.drain(150_000..160_000);
+        let options =
+            SignatureOptions::recommended(basis.len() as u64, SignatureFormat::RkBlake2Sig);
+
+        let mut sig = Vec::new();
+        let sig_stats =
+            crate::mksum::generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        let mut delta = Vec::new();
+        let delta_stats =
+            crate::delta::generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+
+        let mut async_sig = Vec::new();
+        let stats = generate_signature(&mut &basis[..], &options, &mut async_sig)
+            .await
+            .unwrap();
+        assert_eq!(async_sig, sig);
+        assert_eq!(without_elapsed(stats), without_elapsed(sig_stats));
+
+        let mut async_delta = Vec::new();
+        let stats = generate_delta(&mut &sig[..], &mut &new[..], &mut async_delta)
+            .await
+            .unwrap();
+        assert_eq!(async_delta, delta);
+        assert_eq!(without_elapsed(stats), without_elapsed(delta_stats));
+
+        let mut out = Vec::new();
+        let stats = apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut out)
+            .await
+            .unwrap();
+        assert_eq!(out, new);
+        let mut blocking_out = Vec::new();
+        let patch_stats =
+            crate::patch::apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut blocking_out)
+                .unwrap();
+        assert_eq!(without_elapsed(stats), without_elapsed(patch_stats));
+    }
+
+    #[tokio::test]
+    async fn duplex_streams() {
+        let basis = pseudo_random(50_000, 3);
+        let mut new = basis.clone();
+        new.splice(25_000..25_000, pseudo_random(100, 4));
+        let options = SignatureOptions::default();
+
+        // Small pipe buffers force every stage to wait on its peer.
+        let (mut basis_tx, mut basis_rx) = tokio::io::duplex(64);
+        let (mut sig_tx, mut sig_rx) = tokio::io::duplex(64);
+        let feed = async {
+            basis_tx.write_all(&basis).await.unwrap();
+            drop(basis_tx);
+        };
+        let sign = async {
+            generate_signature(&mut basis_rx, &options, &mut sig_tx)
+                .await
+                .unwrap();
+            drop(sig_tx);
+        };
+        let mut new_rx = &new[..];
+        let mut delta = Vec::new();
+        let diff = generate_delta(&mut sig_rx, &mut new_rx, &mut delta);
+        let ((), (), r) = tokio::join!(feed, sign, diff);
+        r.unwrap();
+
+        let (mut delta_tx, mut delta_rx) = tokio::io::duplex(16);
+        let feed = async {
+            delta_tx.write_all(&delta).await.unwrap();
+            drop(delta_tx);
+        };
+        let mut basis = Cursor::new(&basis);
+        let mut out = Vec::new();
+        let patch = apply_patch(&mut basis, &mut delta_rx, &mut out);
+        let ((), r) = tokio::join!(feed, patch);
+        r.unwrap();
+        assert_eq!(out, new);
+    }
+
+    #[tokio::test]
+    async fn monitored() {
+        let basis = pseudo_random(200_000, 19);
+        let mut new = basis.clone();
+        new.splice(100_000..100_000, pseudo_random(100, 20));
+        let options = SignatureOptions::default().with_block_len(1000);
+
+        let mut blocks = Vec::new();
+        let mut observer = |stats: &Stats| blocks.push(stats.blocks);
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut sig = Vec::new();
+        generate_signature_monitored(&mut &basis[..], &options, &mut sig, &mut monitor)
+            .await
+            .unwrap();
+        assert_eq!(blocks, (1..=200).collect::<Vec<_>>());
+
+        let mut chunks = 0;
+        let mut observer = |_: &Stats| chunks += 1;
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut delta = Vec::new();
+        generate_delta_monitored(&mut &sig[..], &mut &new[..], &mut delta, &mut monitor)
+            .await
+            .unwrap();
+        assert!(chunks > 1);
+
+        let mut last = Stats::default();
+        let mut observer = |stats: &Stats| last = *stats;
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut out = Vec::new();
+        apply_patch_monitored(
+            &mut Cursor::new(&basis),
+            &mut &delta[..],
+            &mut out,
+            &mut monitor,
+        )
+        .await
+        .unwrap();
+        assert_eq!(out, new);
+        assert_eq!(last.out_bytes, new.len() as u64);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut monitor = Monitor::new().with_cancel(&cancel);
+        let err =
+            generate_signature_monitored(&mut &basis[..], &options, &mut Vec::new(), &mut monitor)
+                .await
+                .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        let err =
+            generate_delta_monitored(&mut &sig[..], &mut &new[..], &mut Vec::new(), &mut monitor)
+                .await
+                .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        let err = apply_patch_monitored(
+            &mut Cursor::new(&basis),
+            &mut &delta[..],
+            &mut Vec::new(),
+            &mut monitor,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn corrupt_delta() {
+        let err = apply_patch(
+            &mut Cursor::new(b""),
+            &mut &b"rs\x02\x36\x45\x00"[..],
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("truncated command")));
+
+        let err = apply_patch(
+            &mut Cursor::new(b"x"),
+            &mut &b"rs\x02\x36\x45\x00\x02\x00"[..],
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CorruptDelta("copy past the end of the basis")
+        ));
+
+        let err = apply_patch(
+            &mut Cursor::new(b""),
+            &mut &b"rs\x01\x37"[..],
+            &mut Vec::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0x72730137)));
+    }
+}
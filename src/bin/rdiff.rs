@@ -0,0 +1,538 @@
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::process;
+
+use getopts::{Matches, Options};
+
+use rdiff::delta::{generate_delta, Commands};
+use rdiff::mksum::{generate_signature, min_strong_len, SignatureFormat, SignatureOptions};
+use rdiff::patch::apply_patch;
+use rdiff::sumset::Signature;
+use rdiff::{Error, Stats, DEFAULT_BLOCK_LEN};
+
+// librsync's rdiff exits with its rs_result codes.
+const RS_IO_ERROR: i32 = 100;
+const RS_SYNTAX_ERROR: i32 = 101;
+const RS_BAD_MAGIC: i32 = 104;
+const RS_CORRUPT: i32 = 106;
+const RS_PARAM_ERROR: i32 = 108;
+
+/// librsync's minimum strong sum length when the basis size is unknown.
+const DEFAULT_MIN_STRONG_LEN: u32 = 12;
+
+#[derive(Debug)]
+struct Failure {
+    code: i32,
+
+    msg: String,
+}
+
+impl Failure {
+    fn syntax(msg: impl Into<String>) -> Failure {
+        Failure {
+            code: RS_SYNTAX_ERROR,
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Failure {
+        let code = match e {
+            Error::Io(_) | Error::Cancelled => RS_IO_ERROR,
+            Error::BadMagic(_) => RS_BAD_MAGIC,
+            Error::InvalidBlockLen(_) | Error::InvalidStrongLen(_) | Error::InvalidKey => {
+                RS_PARAM_ERROR
+            }
+            Error::CorruptSignature(_) | Error::CorruptDelta(_) => RS_CORRUPT,
+        };
+        Failure {
+            code,
+            msg: e.to_string(),
+        }
+    }
+}
+
+fn io_failure(e: io::Error, path: &str) -> Failure {
+    Failure {
+        code: RS_IO_ERROR,
+        msg: format!("{}: {}", path, e),
+    }
+}
+
+fn is_stdio(name: Option<&str>) -> bool {
+    matches!(name, None | Some("-"))
+}
+
+fn open_input(name: Option<&str>) -> Result<Box<dyn Read>, Failure> {
+    match name {
+        Some(path) if !is_stdio(name) => File::open(path)
+            .map(|f| Box::new(f) as Box<dyn Read>)
+            .map_err(|e| io_failure(e, path)),
+        _ => Ok(Box::new(io::stdin().lock())),
+    }
+}
+
+fn open_output(name: Option<&str>, force: bool) -> Result<Box<dyn Write>, Failure> {
+    match name {
+        Some(path) if !is_stdio(name) => OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .create_new(!force)
+            .open(path)
+            .map(|f| Box::new(f) as Box<dyn Write>)
+            .map_err(|e| io_failure(e, path)),
+        _ => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(matches: &Matches, opt: &str) -> Result<Option<T>, Failure> {
+    match matches.opt_str(opt) {
+        None => Ok(None),
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| Failure::syntax(format!("invalid value for -{}: {}", opt, v))),
+    }
+}
+
+fn signature_format(matches: &Matches) -> Result<SignatureFormat, Failure> {
+    let hash = matches.opt_str("H");
+    let rollsum = matches.opt_str("R");
+    match (
+        hash.as_deref().unwrap_or("blake2"),
+        rollsum.as_deref().unwrap_or("rabinkarp"),
+    ) {
+        ("blake2", "rabinkarp") => Ok(SignatureFormat::RkBlake2Sig),
+        ("md4", "rabinkarp") => Ok(SignatureFormat::RkMd4Sig),
+        ("blake2", "rollsum") => Ok(SignatureFormat::Blake2Sig),
+        ("md4", "rollsum") => Ok(SignatureFormat::Md4Sig),
+        ("blake2" | "md4", r) => Err(Failure::syntax(format!("unknown rollsum: {}", r))),
+        (h, _) => Err(Failure::syntax(format!("unknown hash: {}", h))),
+    }
+}
+
+/// Works out the signature options the way librsync's rdiff does: a block
+/// size of 0 and a sum size of -1 mean the recommended values for the
+/// basis, and a sum size of 0 the longest the hash allows.
+fn signature_options(
+    matches: &Matches,
+    basis_len: Option<u64>,
+) -> Result<SignatureOptions, Failure> {
+    let magic = signature_format(matches)?;
+    let block_len: u32 = parse_num(matches, "b")?.unwrap_or(0);
+    let strong_len: i64 = parse_num(matches, "S")?.unwrap_or(0);
+
+    let recommended = match basis_len {
+        Some(l) => SignatureOptions::recommended(l, magic),
+        None => SignatureOptions {
+            magic,
+            block_len: DEFAULT_BLOCK_LEN,
+            strong_len: DEFAULT_MIN_STRONG_LEN.min(magic.max_strong_len()),
+            key: None,
+        },
+    };
+    let block_len = if block_len == 0 {
+        recommended.block_len
+    } else {
+        block_len
+    };
+    let strong_len = match strong_len {
+        0 => magic.max_strong_len(),
+        -1 => match basis_len {
+            Some(l) => min_strong_len(l, block_len).min(magic.max_strong_len()),
+            None => recommended.strong_len,
+        },
+        l => {
+            u32::try_from(l).map_err(|_| Failure::syntax(format!("invalid value for -S: {}", l)))?
+        }
+    };
+    Ok(SignatureOptions {
+        magic,
+        block_len,
+        strong_len,
+        key: None,
+    })
+}
+
+fn check_args(args: &[String], min: usize, max: usize) -> Result<(), Failure> {
+    if args.len() < min {
+        Err(Failure::syntax(format!("{} needs more arguments", args[0])))
+    } else if args.len() > max {
+        Err(Failure::syntax(format!(
+            "{} has too many arguments",
+            args[0]
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Prints each command of a delta, then how many of each kind there were and
+/// how many bytes they make up.
+fn explain_delta(delta: Box<dyn Read>) -> Result<(), Failure> {
+    let out = &mut io::stdout().lock();
+    let mut stats = Stats::default();
+    for command in Commands::new(delta)? {
+        let command = command?;
+        writeln!(out, "{}", command).map_err(|e| io_failure(e, "stdout"))?;
+        stats.count_command(&command);
+    }
+    writeln!(
+        out,
+        "total: {} LITERAL ({} bytes), {} COPY ({} bytes), new file {} bytes",
+        stats.lit_cmds,
+        stats.lit_bytes,
+        stats.copy_cmds,
+        stats.copy_bytes,
+        stats.lit_bytes + stats.copy_bytes
+    )
+    .map_err(|e| io_failure(e, "stdout"))
+}
+
+fn run(args: &[String], stderr: &mut dyn Write) -> Result<(), Failure> {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "Show this help message");
+    opts.optflag("V", "version", "Show program version");
+    opts.optflag("s", "statistics", "Show performance statistics");
+    opts.optflag("f", "force", "Force overwriting existing files");
+    opts.optflag("", "blocks", "List each block's sums when inspecting");
+    opts.optopt("H", "hash", "Hash algorithm: blake2 (default), md4", "ALG");
+    opts.optopt(
+        "R",
+        "rollsum",
+        "Rollsum algorithm: rabinkarp (default), rollsum",
+        "ALG",
+    );
+    opts.optopt(
+        "b",
+        "block-size",
+        "Signature block size, 0 (default) for recommended",
+        "BYTES",
+    );
+    opts.optopt(
+        "S",
+        "sum-size",
+        "Set signature strength, 0 (default) for max, -1 for min",
+        "BYTES",
+    );
+
+    let matches = opts
+        .parse(&args[1..])
+        .map_err(|e| Failure::syntax(e.to_string()))?;
+    if matches.opt_present("h") {
+        let brief = "Usage: rdiff [OPTIONS] signature [BASIS [SIGNATURE]]\n             \
+                     [OPTIONS] delta SIGNATURE [NEWFILE [DELTA]]\n             \
+                     [OPTIONS] patch BASIS [DELTA [NEWFILE]]\n             \
+                     [OPTIONS] inspect [SIGNATURE]\n             \
+                     [OPTIONS] explain-delta [DELTA]";
+        print!("{}", opts.usage(brief));
+        return Ok(());
+    }
+    if matches.opt_present("V") {
+        println!("rdiff {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    let free = &matches.free;
+    if free.is_empty() {
+        return Err(Failure::syntax(
+            "you must specify an action: signature, delta, patch, inspect or explain-delta",
+        ));
+    }
+    let arg = |i: usize| free.get(i).map(String::as_str);
+    let force = matches.opt_present("f");
+
+    let stats = match free[0].as_str() {
+        "signature" => {
+            check_args(free, 1, 3)?;
+            let basis_len = match arg(1) {
+                Some(path) if !is_stdio(arg(1)) => Some(
+                    std::fs::metadata(path)
+                        .map_err(|e| io_failure(e, path))?
+                        .len(),
+                ),
+                _ => None,
+            };
+            let options = signature_options(&matches, basis_len)?;
+            let mut basis = open_input(arg(1))?;
+            let mut sig = open_output(arg(2), force)?;
+            generate_signature(&mut basis, &options, &mut sig)?
+        }
+        "delta" => {
+            check_args(free, 2, 4)?;
+            let mut sig = open_input(arg(1))?;
+            let mut new_file = open_input(arg(2))?;
+            let mut delta = open_output(arg(3), force)?;
+            generate_delta(&mut sig, &mut new_file, &mut delta)?
+        }
+        "patch" => {
+            check_args(free, 2, 4)?;
+            let basis_path = free[1].as_str();
+            if basis_path == "-" {
+                return Err(Failure {
+                    code: RS_PARAM_ERROR,
+                    msg: "basis file must be a seekable file, not stdin".to_string(),
+                });
+            }
+            let mut basis = File::open(basis_path).map_err(|e| io_failure(e, basis_path))?;
+            let mut delta = open_input(arg(2))?;
+            let mut new_file = open_output(arg(3), force)?;
+            apply_patch(&mut basis, &mut delta, &mut new_file)?
+        }
+        "inspect" => {
+            check_args(free, 1, 2)?;
+            let sig = Signature::load(&mut open_input(arg(1))?)?;
+            sig.dump(&mut io::stdout().lock(), matches.opt_present("blocks"))?;
+            return Ok(());
+        }
+        "explain-delta" => {
+            check_args(free, 1, 2)?;
+            return explain_delta(open_input(arg(1))?);
+        }
+        action => {
+            return Err(Failure::syntax(format!("unknown action: {}", action)));
+        }
+    };
+
+    if matches.opt_present("s") {
+        writeln!(stderr, "rdiff: {} statistics: {}", free[0], stats)
+            .map_err(|e| io_failure(e, "stderr"))?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if let Err(e) = run(&args, &mut io::stderr()) {
+        eprintln!("rdiff: {}", e);
+        if e.code == RS_SYNTAX_ERROR {
+            eprintln!("Try `rdiff --help' for more information.");
+        }
+        process::exit(e.code);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rdiff::Stats;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rdiff-cli-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn rdiff(args: &[&str]) -> Result<(), Failure> {
+        rdiff_stderr(args).map(|_| ())
+    }
+
+    /// Runs rdiff and returns what it wrote to stderr.
+    fn rdiff_stderr(args: &[&str]) -> Result<String, Failure> {
+        let args: Vec<String> = ["rdiff"]
+            .iter()
+            .chain(args)
+            .map(|s| s.to_string())
+            .collect();
+        let mut stderr = Vec::new();
+        run(&args, &mut stderr)?;
+        Ok(String::from_utf8(stderr).unwrap())
+    }
+
+    /// The statistics line rdiff prints for `action`, up to the timing, which differs
+    /// from run to run.
+    fn statistics_line(action: &str, stats: &Stats) -> String {
+        let line = format!("rdiff: {} statistics: {}", action, stats);
+        line[..line.find(" elapsed=").unwrap()].to_string()
+    }
+
+    fn test_files() -> (Vec<u8>, Vec<u8>) {
+        let basis: Vec<u8> = (0..50_000u32).map(|i| (i * 31 % 255) as u8).collect();
+        let mut new = basis.clone();
+        new.splice(20_000..20_000, b"inserted".iter().copied());
+        (basis, new)
+    }
+
+    fn parse_options(args: &[&str], basis_len: Option<u64>) -> SignatureOptions {
+        let mut opts = Options::new();
+        for o in ["H", "R", "b", "S"] {
+            opts.optopt(o, "", "", "");
+        }
+        let matches = opts.parse(args).unwrap();
+        signature_options(&matches, basis_len).unwrap()
+    }
+
+    #[test]
+    pub fn option_defaults() {
+        let options = parse_options(&[], Some(1 << 30));
+        assert_eq!(options.magic, SignatureFormat::RkBlake2Sig);
+        assert_eq!(options.block_len, 32768);
+        assert_eq!(options.strong_len, 32);
+
+        let options = parse_options(&["-H", "md4", "-R", "rollsum", "-S", "-1"], None);
+        assert_eq!(options.magic, SignatureFormat::Md4Sig);
+        assert_eq!(options.block_len, DEFAULT_BLOCK_LEN);
+        assert_eq!(options.strong_len, 12);
+
+        let options = parse_options(&["-b", "1024", "-S", "-1"], Some(1 << 30));
+        assert_eq!(options.block_len, 1024);
+        assert_eq!(options.strong_len, 9);
+    }
+
+    #[test]
+    pub fn round_trip_files() {
+        let dir = scratch_dir("round-trip");
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (basis, new) = test_files();
+        fs::write(path("basis"), &basis).unwrap();
+        fs::write(path("new"), &new).unwrap();
+
+        rdiff(&["signature", &path("basis"), &path("sig")]).unwrap();
+        rdiff(&["-s", "delta", &path("sig"), &path("new"), &path("delta")]).unwrap();
+        rdiff(&["patch", &path("basis"), &path("delta"), &path("out")]).unwrap();
+        assert_eq!(fs::read(path("out")).unwrap(), new);
+        assert!(fs::metadata(path("delta")).unwrap().len() < 1000);
+
+        let err = rdiff(&["patch", &path("basis"), &path("delta"), &path("out")]).unwrap_err();
+        assert_eq!(err.code, RS_IO_ERROR);
+        rdiff(&["-f", "patch", &path("basis"), &path("delta"), &path("out")]).unwrap();
+
+        let err = rdiff(&["delta", &path("basis"), &path("new"), &path("delta2")]).unwrap_err();
+        assert_eq!(err.code, RS_BAD_MAGIC);
+
+        rdiff(&["--blocks", "inspect", &path("sig")]).unwrap();
+        let err = rdiff(&["inspect", &path("basis")]).unwrap_err();
+        assert_eq!(err.code, RS_BAD_MAGIC);
+        rdiff(&["explain-delta", &path("delta")]).unwrap();
+        let err = rdiff(&["explain-delta", &path("sig")]).unwrap_err();
+        assert_eq!(err.code, RS_BAD_MAGIC);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn outputs_match_library() {
+        let dir = scratch_dir("outputs");
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (basis, new) = test_files();
+        fs::write(path("basis"), &basis).unwrap();
+        fs::write(path("new"), &new).unwrap();
+
+        for (args, options) in [
+            (
+                vec![],
+                SignatureOptions::recommended(basis.len() as u64, SignatureFormat::RkBlake2Sig)
+                    .with_strong_len(32),
+            ),
+            (
+                vec!["-H", "md4", "-R", "rollsum", "-b", "700", "-S", "-1"],
+                SignatureOptions::recommended(basis.len() as u64, SignatureFormat::Md4Sig)
+                    .with_block_len(700)
+                    .with_strong_len(min_strong_len(basis.len() as u64, 700)),
+            ),
+        ] {
+            let run = |action: &[&str]| {
+                let args: Vec<&str> = ["-f"].iter().chain(&args).chain(action).copied().collect();
+                rdiff(&args).unwrap();
+            };
+            run(&["signature", &path("basis"), &path("sig")]);
+            run(&["delta", &path("sig"), &path("new"), &path("delta")]);
+            run(&["patch", &path("basis"), &path("delta"), &path("out")]);
+
+            let mut sig = Vec::new();
+            generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+            assert_eq!(fs::read(path("sig")).unwrap(), sig);
+            let mut delta = Vec::new();
+            generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+            assert_eq!(fs::read(path("delta")).unwrap(), delta);
+            let mut out = Vec::new();
+            apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut out).unwrap();
+            assert_eq!(fs::read(path("out")).unwrap(), out);
+            assert_eq!(out, new);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn statistics() {
+        let dir = scratch_dir("statistics");
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (basis, new) = test_files();
+        fs::write(path("basis"), &basis).unwrap();
+        fs::write(path("new"), &new).unwrap();
+        let options =
+            SignatureOptions::recommended(basis.len() as u64, SignatureFormat::RkBlake2Sig)
+                .with_strong_len(32);
+
+        let mut sig = Vec::new();
+        let stats = generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        let stderr = rdiff_stderr(&["-s", "signature", &path("basis"), &path("sig")]).unwrap();
+        assert!(
+            stderr.starts_with(&statistics_line("signature", &stats)),
+            "{}",
+            stderr
+        );
+
+        let mut delta = Vec::new();
+        let stats = generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+        let stderr = rdiff_stderr(&[
+            "--statistics",
+            "delta",
+            &path("sig"),
+            &path("new"),
+            &path("delta"),
+        ])
+        .unwrap();
+        assert!(
+            stderr.starts_with(&statistics_line("delta", &stats)),
+            "{}",
+            stderr
+        );
+        assert_eq!(stderr.lines().count(), 1);
+
+        let mut out = Vec::new();
+        let stats = apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut out).unwrap();
+        let stderr =
+            rdiff_stderr(&["-s", "patch", &path("basis"), &path("delta"), &path("out")]).unwrap();
+        assert!(
+            stderr.starts_with(&statistics_line("patch", &stats)),
+            "{}",
+            stderr
+        );
+
+        // Without -s nothing is printed.
+        let stderr =
+            rdiff_stderr(&["-f", "patch", &path("basis"), &path("delta"), &path("out")]).unwrap();
+        assert_eq!(stderr, "");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    pub fn syntax_errors() {
+        assert_eq!(rdiff(&[]).unwrap_err().code, RS_SYNTAX_ERROR);
+        assert_eq!(rdiff(&["frobnicate"]).unwrap_err().code, RS_SYNTAX_ERROR);
+        assert_eq!(rdiff(&["delta"]).unwrap_err().code, RS_SYNTAX_ERROR);
+        assert_eq!(
+            rdiff(&["signature", "a", "b", "c"]).unwrap_err().code,
+            RS_SYNTAX_ERROR
+        );
+        assert_eq!(
+            rdiff(&["-H", "sha1", "signature"]).unwrap_err().code,
+            RS_SYNTAX_ERROR
+        );
+        assert_eq!(rdiff(&["patch", "-"]).unwrap_err().code, RS_PARAM_ERROR);
+    }
+}
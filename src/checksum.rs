@@ -0,0 +1,264 @@
+use blake2::digest::consts::U32;
+use blake2::digest::Mac;
+use blake2::{Blake2b, Blake2bMac, Digest};
+
+/// A strong checksum that confirms a block once its weak sum has matched.
+pub trait StrongHash: Clone {
+    /// Length of the full digest; signatures may store a truncated prefix of it.
+    const MAX_LEN: usize;
+
+    fn new() -> Self;
+
+    fn update(&mut self, buf: &[u8]);
+
+    /// Writes the first `out.len()` bytes of the digest, which must not exceed `MAX_LEN`.
+    fn finalize_into(self, out: &mut [u8]);
+
+    fn digest(buf: &[u8], out: &mut [u8]) {
+        let mut h = Self::new();
+        h.update(buf);
+        h.finalize_into(out);
+    }
+}
+
+/// BLAKE2b-256, used by the BLAKE2 signature formats, and keyed for the keyed ones.
+#[derive(Clone)]
+pub struct Blake2(Blake2State);
+
+#[derive(Clone)]
+enum Blake2State {
+    Plain(Blake2b<U32>),
+    Keyed(Blake2bMac<U32>),
+}
+
+impl Blake2 {
+    /// BLAKE2b-256 in keyed mode; `key` must be at most 64 bytes.
+    pub fn keyed(key: &[u8]) -> Blake2 {
+        // `Mac::new_from_slice` in blake2 0.10.4 passes the key as the salt.
+        let mac = Blake2bMac::new_with_salt_and_personal(key, &[], &[])
+            .expect("BLAKE2b keys are at most 64 bytes");
+        Blake2(Blake2State::Keyed(mac))
+    }
+}
+
+impl StrongHash for Blake2 {
+    const MAX_LEN: usize = 32;
+
+    fn new() -> Blake2 {
+        Blake2(Blake2State::Plain(Blake2b::new()))
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        match &mut self.0 {
+            Blake2State::Plain(h) => Digest::update(h, buf),
+            Blake2State::Keyed(h) => Mac::update(h, buf),
+        }
+    }
+
+    fn finalize_into(self, out: &mut [u8]) {
+        let d = match self.0 {
+            Blake2State::Plain(h) => h.finalize(),
+            Blake2State::Keyed(h) => h.finalize().into_bytes(),
+        };
+        out.copy_from_slice(&d[..out.len()]);
+    }
+}
+
+/// MD4, used by `Md4Sig` and `RkMd4Sig` signatures.
+#[derive(Clone)]
+pub struct Md4(md4::Md4);
+
+impl StrongHash for Md4 {
+    const MAX_LEN: usize = 16;
+
+    fn new() -> Md4 {
+        Md4(md4::Md4::new())
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf);
+    }
+
+    fn finalize_into(self, out: &mut [u8]) {
+        out.copy_from_slice(&self.0.finalize()[..out.len()]);
+    }
+}
+
+/// BLAKE3, used by `Blake3Sig` and `RkBlake3Sig` signatures, which librsync does
+/// not understand.
+#[cfg(feature = "blake3")]
+#[derive(Clone)]
+pub struct Blake3(blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl StrongHash for Blake3 {
+    const MAX_LEN: usize = 32;
+
+    fn new() -> Blake3 {
+        Blake3(blake3::Hasher::new())
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf);
+    }
+
+    fn finalize_into(self, out: &mut [u8]) {
+        out.copy_from_slice(&self.0.finalize().as_bytes()[..out.len()]);
+    }
+}
+
+/// SHA-256, used by `Sha256Sig` and `RkSha256Sig` signatures, which librsync does
+/// not understand.
+#[cfg(feature = "sha2")]
+#[derive(Clone)]
+pub struct Sha256(sha2::Sha256);
+
+#[cfg(feature = "sha2")]
+impl StrongHash for Sha256 {
+    const MAX_LEN: usize = 32;
+
+    fn new() -> Sha256 {
+        Sha256(sha2::Sha256::new())
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf);
+    }
+
+    fn finalize_into(self, out: &mut [u8]) {
+        out.copy_from_slice(&self.0.finalize()[..out.len()]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::delta::{generate_delta_from_signature, generate_delta_with_hash};
+    use crate::mksum::{generate_signature_with_hash, SignatureFormat, SignatureOptions};
+    use crate::patch::apply_patch;
+    use crate::sumset::Signature;
+    use crate::testutil::pseudo_random;
+    use std::io::Cursor;
+
+    fn hex_digest<H: StrongHash>(buf: &[u8]) -> String {
+        let mut out = vec![0u8; H::MAX_LEN];
+        H::digest(buf, &mut out);
+        out.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn check_pieces_and_truncation<H: StrongHash>() {
+        let buf: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let mut full = vec![0u8; H::MAX_LEN];
+        H::digest(&buf, &mut full);
+
+        let mut h = H::new();
+        h.update(&buf[..300]);
+        h.update(&buf[300..]);
+        let mut pieces = vec![0u8; H::MAX_LEN];
+        h.finalize_into(&mut pieces);
+        assert_eq!(pieces, full);
+
+        let mut short = [0u8; 5];
+        H::digest(&buf, &mut short);
+        assert_eq!(short, full[..5]);
+    }
+
+    #[test]
+    pub fn known_digests() {
+        assert_eq!(
+            hex_digest::<Md4>(b"abc"),
+            "a448017aaf21d8525fc10ae87aa6729d"
+        );
+        assert_eq!(
+            hex_digest::<Blake2>(b"abc"),
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319"
+        );
+        #[cfg(feature = "blake3")]
+        assert_eq!(
+            hex_digest::<Blake3>(b"abc"),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        #[cfg(feature = "sha2")]
+        assert_eq!(
+            hex_digest::<Sha256>(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    pub fn keyed_blake2() {
+        // BLAKE2b-256 keyed with bytes 0..64, over the bytes 0..3, as computed by
+        // Python's hashlib.blake2b(bytes(range(3)), key=bytes(range(64)), digest_size=32).
+        let key: Vec<u8> = (0..64).collect();
+        let mut out = [0u8; 32];
+        let mut h = Blake2::keyed(&key);
+        h.update(&[0, 1, 2]);
+        h.finalize_into(&mut out);
+        let hex: String = out.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            "3e57c5ab79418defd6e252719a380096d9abf1901db38e0be7d404eb7206c0dc"
+        );
+
+        let mut other = [0u8; 32];
+        let mut h = Blake2::keyed(&key[..16]);
+        h.update(&[0, 1, 2]);
+        h.finalize_into(&mut other);
+        assert_ne!(other, out);
+        Blake2::digest(&[0, 1, 2], &mut other);
+        assert_ne!(other, out);
+    }
+
+    #[test]
+    pub fn pieces_and_truncation() {
+        check_pieces_and_truncation::<Md4>();
+        check_pieces_and_truncation::<Blake2>();
+        #[cfg(feature = "blake3")]
+        check_pieces_and_truncation::<Blake3>();
+        #[cfg(feature = "sha2")]
+        check_pieces_and_truncation::<Sha256>();
+    }
+
+    fn round_trip_with_hash<H: StrongHash + Send + Sync + 'static>(
+        basis: &[u8],
+        new: &[u8],
+        magic: SignatureFormat,
+    ) -> Vec<u8> {
+        let options = SignatureOptions::default()
+            .with_magic(magic)
+            .with_block_len(512)
+            .with_strong_len(8);
+        let mut sig = Vec::new();
+        generate_signature_with_hash::<H>(&mut &basis[..], &options, &mut sig).unwrap();
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        let mut delta = Vec::new();
+        generate_delta_with_hash::<H>(&sig, &mut &new[..], &mut delta).unwrap();
+        // Only the inserted bytes should have gone out as literals.
+        assert!(delta.len() < 1000);
+        // The header names the hash, so the plain functions pick it too.
+        let mut plain = Vec::new();
+        generate_delta_from_signature(&sig, &mut &new[..], &mut plain).unwrap();
+        assert_eq!(plain, delta);
+        let mut out = Vec::new();
+        apply_patch(&mut Cursor::new(basis), &mut &delta[..], &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    pub fn other_hash_round_trips() {
+        let basis = pseudo_random(20_000, 8);
+        let mut new = basis.clone();
+        new.splice(5_000..5_000, pseudo_random(100, 9));
+        for magic in [SignatureFormat::Md4Sig, SignatureFormat::RkMd4Sig] {
+            assert_eq!(round_trip_with_hash::<Md4>(&basis, &new, magic), new);
+        }
+        #[cfg(feature = "blake3")]
+        for magic in [SignatureFormat::Blake3Sig, SignatureFormat::RkBlake3Sig] {
+            assert_eq!(round_trip_with_hash::<Blake3>(&basis, &new, magic), new);
+        }
+        #[cfg(feature = "sha2")]
+        for magic in [SignatureFormat::Sha256Sig, SignatureFormat::RkSha256Sig] {
+            assert_eq!(round_trip_with_hash::<Sha256>(&basis, &new, magic), new);
+        }
+    }
+}
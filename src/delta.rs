@@ -0,0 +1,823 @@
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::time::Instant;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use cast::usize;
+
+use crate::checksum::StrongHash;
+use crate::error::{Error, Result};
+use crate::mksum::{fill_buffer, write_u32be, UseHash, RS_MAX_STRONG_SUM_LENGTH};
+use crate::patch::{copy_exact, read_magic};
+use crate::progress::Monitor;
+use crate::rabinkarp::RabinKarp;
+use crate::rollsum::{Rollsum, Window};
+use crate::stats::{Counted, Stats};
+use crate::sumset::Signature;
+
+pub const DELTA_MAGIC: u32 = 0x72730236;
+
+const OP_END: u8 = 0x00;
+const OP_LITERAL_1: u8 = 0x01;
+const OP_LITERAL_64: u8 = 0x40;
+const OP_LITERAL_N1: u8 = 0x41;
+const OP_LITERAL_N8: u8 = 0x44;
+const OP_COPY_N1_N1: u8 = 0x45;
+const OP_COPY_N8_N8: u8 = 0x54;
+
+pub(crate) const INPUT_CHUNK_LEN: usize = 1 << 16;
+const MAX_LITERAL_LEN: usize = 1 << 16;
+
+/// One command of a delta: the literal's data follows it in the delta, and a
+/// copy takes `len` bytes of the basis from `offset`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    End,
+    Literal { len: u64 },
+    Copy { offset: u64, len: u64 },
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Command::End => write!(f, "END"),
+            Command::Literal { len } => write!(f, "LITERAL len={}", len),
+            Command::Copy { offset, len } => write!(f, "COPY offset={} len={}", offset, len),
+        }
+    }
+}
+
+/// The commands of a delta, up to and including `Command::End`, with the
+/// literal data skipped.
+pub struct Commands<R: Read> {
+    delta: BufReader<R>,
+
+    done: bool,
+}
+
+impl<R: Read> Commands<R> {
+    /// Reads the magic at the start of `delta`.
+    pub fn new(delta: R) -> Result<Commands<R>> {
+        let mut delta = BufReader::new(delta);
+        read_magic(&mut delta)?;
+        Ok(Commands { delta, done: false })
+    }
+
+    fn next_command(&mut self) -> Result<Command> {
+        let command = Command::read_from(&mut self.delta)?;
+        match command {
+            Command::End => self.done = true,
+            Command::Literal { len } => copy_exact(
+                &mut self.delta,
+                len,
+                &mut io::sink(),
+                Error::CorruptDelta("truncated literal data"),
+            )?,
+            Command::Copy { .. } => {}
+        }
+        Ok(command)
+    }
+}
+
+impl<R: Read> Iterator for Commands<R> {
+    type Item = Result<Command>;
+
+    fn next(&mut self) -> Option<Result<Command>> {
+        if self.done {
+            return None;
+        }
+        let command = self.next_command();
+        if command.is_err() {
+            self.done = true;
+        }
+        Some(command)
+    }
+}
+
+/// Index (0..=3) of the smallest of the 1, 2, 4 or 8 byte encodings that can hold `v`.
+fn int_width(v: u64) -> u8 {
+    if v <= u8::MAX as u64 {
+        0
+    } else if v <= u16::MAX as u64 {
+        1
+    } else if v <= u32::MAX as u64 {
+        2
+    } else {
+        3
+    }
+}
+
+fn write_int(out: &mut dyn Write, v: u64, width: u8) -> io::Result<()> {
+    match width {
+        0 => out.write_u8(v as u8),
+        1 => out.write_u16::<BigEndian>(v as u16),
+        2 => out.write_u32::<BigEndian>(v as u32),
+        _ => out.write_u64::<BigEndian>(v),
+    }
+}
+
+fn read_int(inf: &mut dyn Read, width: u8) -> Result<u64> {
+    match width {
+        0 => inf.read_u8().map(u64::from),
+        1 => inf.read_u16::<BigEndian>().map(u64::from),
+        2 => inf.read_u32::<BigEndian>().map(u64::from),
+        _ => inf.read_u64::<BigEndian>(),
+    }
+    .map_err(|e| Error::truncated(e, Error::CorruptDelta("truncated command")))
+}
+
+/// Length of the opcode and operands of the command starting with `op`.
+pub(crate) fn command_len(op: u8) -> usize {
+    match op {
+        OP_LITERAL_N1..=OP_LITERAL_N8 => 1 + (1 << (op - OP_LITERAL_N1)),
+        OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+            let w = op - OP_COPY_N1_N1;
+            1 + (1 << (w / 4)) + (1 << (w % 4))
+        }
+        _ => 1,
+    }
+}
+
+impl Command {
+    /// Decodes the command at the start of `buf`, returning it with its encoded
+    /// length, or `None` if `buf` does not hold all of it yet.
+    pub(crate) fn decode(buf: &[u8]) -> Result<Option<(Command, usize)>> {
+        match buf.first() {
+            Some(&op) if buf.len() >= command_len(op) => {
+                let c = Command::read_from(&mut &buf[..])?;
+                Ok(Some((c, command_len(op))))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the opcode and operands; the data of a literal is left for the caller.
+    pub(crate) fn read_from(inf: &mut dyn Read) -> Result<Command> {
+        let op = inf
+            .read_u8()
+            .map_err(|e| Error::truncated(e, Error::CorruptDelta("missing end command")))?;
+        match op {
+            OP_END => Ok(Command::End),
+            OP_LITERAL_1..=OP_LITERAL_64 => Ok(Command::Literal {
+                len: (op - OP_LITERAL_1 + 1) as u64,
+            }),
+            OP_LITERAL_N1..=OP_LITERAL_N8 => Ok(Command::Literal {
+                len: read_int(inf, op - OP_LITERAL_N1)?,
+            }),
+            OP_COPY_N1_N1..=OP_COPY_N8_N8 => {
+                let w = op - OP_COPY_N1_N1;
+                let offset = read_int(inf, w / 4)?;
+                let len = read_int(inf, w % 4)?;
+                Ok(Command::Copy { offset, len })
+            }
+            _ => Err(Error::CorruptDelta("unknown command")),
+        }
+    }
+
+    /// Writes the opcode and operands; the data of a literal is written by the caller.
+    pub(crate) fn write_to(&self, out: &mut dyn Write) -> io::Result<()> {
+        match *self {
+            Command::End => out.write_u8(OP_END),
+            Command::Literal { len } if len >= 1 && len <= (OP_LITERAL_64 as u64) => {
+                out.write_u8(OP_LITERAL_1 + (len as u8) - 1)
+            }
+            Command::Literal { len } => {
+                let w = int_width(len);
+                out.write_u8(OP_LITERAL_N1 + w)?;
+                write_int(out, len, w)
+            }
+            Command::Copy { offset, len } => {
+                let (ow, lw) = (int_width(offset), int_width(len));
+                out.write_u8(OP_COPY_N1_N1 + ow * 4 + lw)?;
+                write_int(out, offset, ow)?;
+                write_int(out, len, lw)
+            }
+        }
+    }
+}
+
+pub(crate) trait Scan {
+    /// Matches `data` against the signature, writing out commands once they are
+    /// settled; `eof` flushes everything still held back.
+    fn scan(&mut self, data: &[u8], eof: bool, out: &mut dyn Write) -> Result<()>;
+
+    /// Like `scan`, but matches `data` where it lies instead of copying it. Each
+    /// call's `data` must start with the previous call's, so a caller holding all
+    /// of the input can report progress between longer and longer prefixes of it.
+    #[cfg(any(feature = "mmap", test))]
+    fn scan_in_place(&mut self, data: &[u8], eof: bool, out: &mut dyn Write) -> Result<()>;
+
+    /// Input, block, command and false match counts so far.
+    fn stats(&self) -> &Stats;
+}
+
+pub(crate) fn scanner(sig: &Signature) -> Box<dyn Scan + Send + '_> {
+    sig.magic().use_hash(sig.key(), MakeScanner(sig))
+}
+
+struct MakeScanner<'s>(&'s Signature);
+
+impl<'s> UseHash for MakeScanner<'s> {
+    type Output = Box<dyn Scan + Send + 's>;
+
+    fn with<H: StrongHash + Send + Sync + 'static>(self, hash: H) -> Self::Output {
+        scanner_with(self.0, hash)
+    }
+}
+
+/// `hash` is cloned for each window looked up, so it may carry a key.
+pub(crate) fn scanner_with<H: StrongHash + Send + 'static>(
+    sig: &Signature,
+    hash: H,
+) -> Box<dyn Scan + Send + '_> {
+    if sig.magic().is_rabinkarp() {
+        Box::new(Scanner::<RabinKarp, H>::new(sig, hash))
+    } else {
+        Box::new(Scanner::<Window, H>::new(sig, hash))
+    }
+}
+
+/// Scans the new file a chunk at a time, keeping just enough of it buffered
+/// to hold the pending literal and the current window.
+struct Scanner<'s, R, H> {
+    sig: &'s Signature,
+
+    buf: Vec<u8>,
+
+    /// Start of the bytes not yet covered by an emitted command.
+    lit_start: usize,
+
+    /// Start of the current window.
+    pos: usize,
+
+    /// Length of the current window, or 0 if it has to be recomputed.
+    window_len: usize,
+
+    /// Whether the current window has already been looked up.
+    checked: bool,
+
+    sum: R,
+
+    /// A copy command held back so that following contiguous blocks can be merged into it.
+    pending_copy: Option<(u64, u64)>,
+
+    hash: H,
+
+    stats: Stats,
+}
+
+impl<'s, R: Rollsum + Default, H: StrongHash> Scanner<'s, R, H> {
+    fn new(sig: &'s Signature, hash: H) -> Scanner<'s, R, H> {
+        Scanner {
+            sig,
+            buf: Vec::new(),
+            lit_start: 0,
+            pos: 0,
+            window_len: 0,
+            checked: false,
+            sum: R::default(),
+            pending_copy: None,
+            hash,
+            stats: Stats {
+                blocks: sig.len() as u64,
+                ..Stats::default()
+            },
+        }
+    }
+
+    fn find_match(&mut self, buf: &[u8]) -> Option<usize> {
+        let weak = self.sum.digest();
+        if self.sig.index().candidates(weak).is_empty() {
+            return None;
+        }
+        let mut strong = [0u8; RS_MAX_STRONG_SUM_LENGTH];
+        let strong = &mut strong[..usize(self.sig.strong_len())];
+        let mut h = self.hash.clone();
+        h.update(&buf[self.pos..self.pos + self.window_len]);
+        h.finalize_into(strong);
+
+        // Prefer the block that would extend the pending copy.
+        let block_len = self.sig.block_len() as u64;
+        let next = self
+            .pending_copy
+            .map(|(offset, len)| offset + len)
+            .filter(|end| end % block_len == 0)
+            .map(|end| (end / block_len) as usize);
+        let block = self.sig.find_block(weak, strong, next);
+        if block.is_none() {
+            self.stats.false_matches += 1;
+        }
+        block
+    }
+
+    fn push_copy(&mut self, offset: u64, len: u64, out: &mut dyn Write) -> Result<()> {
+        if let Some((pending_offset, pending_len)) = self.pending_copy {
+            if pending_offset + pending_len == offset {
+                self.pending_copy = Some((pending_offset, pending_len + len));
+                return Ok(());
+            }
+            self.flush_copy(out)?;
+        }
+        self.pending_copy = Some((offset, len));
+        Ok(())
+    }
+
+    fn flush_copy(&mut self, out: &mut dyn Write) -> Result<()> {
+        if let Some((offset, len)) = self.pending_copy.take() {
+            let command = Command::Copy { offset, len };
+            command.write_to(out)?;
+            self.stats.count_command(&command);
+        }
+        Ok(())
+    }
+
+    fn flush_literal(&mut self, buf: &[u8], out: &mut dyn Write) -> Result<()> {
+        if self.pos == self.lit_start {
+            return Ok(());
+        }
+        self.flush_copy(out)?;
+        let literal = &buf[self.lit_start..self.pos];
+        let command = Command::Literal {
+            len: literal.len() as u64,
+        };
+        command.write_to(out)?;
+        out.write_all(literal)?;
+        self.stats.count_command(&command);
+        self.lit_start = self.pos;
+        Ok(())
+    }
+
+    /// Matches `buf` from `pos` onwards, leaving `lit_start` at the first byte
+    /// that still has to be kept.
+    fn run(&mut self, buf: &[u8], eof: bool, out: &mut dyn Write) -> Result<()> {
+        let block_len = usize(self.sig.block_len());
+
+        loop {
+            let avail = buf.len() - self.pos;
+            if self.window_len == 0 {
+                if avail == 0 || (avail < block_len && !eof) {
+                    break;
+                }
+                self.window_len = avail.min(block_len);
+                self.sum = R::default();
+                self.sum.update(&buf[self.pos..self.pos + self.window_len]);
+            } else if self.checked {
+                if avail > self.window_len {
+                    self.sum
+                        .rotate(buf[self.pos], buf[self.pos + self.window_len]);
+                } else if eof {
+                    // Only the last block of the basis can be short, so at the
+                    // end of the input keep shrinking the window to find it.
+                    self.sum.roll_out(buf[self.pos]);
+                    self.window_len -= 1;
+                } else {
+                    break;
+                }
+                self.pos += 1;
+                if self.pos - self.lit_start >= MAX_LITERAL_LEN {
+                    self.flush_literal(buf, out)?;
+                }
+                if self.window_len == 0 {
+                    continue;
+                }
+            }
+
+            self.checked = true;
+            if let Some(block) = self.find_match(buf) {
+                self.flush_literal(buf, out)?;
+                self.push_copy(
+                    block as u64 * self.sig.block_len() as u64,
+                    self.window_len as u64,
+                    out,
+                )?;
+                self.pos += self.window_len;
+                self.lit_start = self.pos;
+                self.window_len = 0;
+                self.checked = false;
+            }
+        }
+
+        if eof {
+            self.flush_literal(buf, out)?;
+            self.flush_copy(out)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Rollsum + Default, H: StrongHash> Scan for Scanner<'_, R, H> {
+    fn scan(&mut self, data: &[u8], eof: bool, out: &mut dyn Write) -> Result<()> {
+        self.stats.in_bytes += data.len() as u64;
+        if eof && self.buf.is_empty() {
+            // Nothing is held back, so match the data where it lies.
+            return self.run(data, eof, out);
+        }
+        let mut buf = mem::take(&mut self.buf);
+        buf.extend_from_slice(data);
+        self.run(&buf, eof, out)?;
+
+        buf.drain(..self.lit_start);
+        self.pos -= self.lit_start;
+        self.lit_start = 0;
+        self.buf = buf;
+        Ok(())
+    }
+
+    #[cfg(any(feature = "mmap", test))]
+    fn scan_in_place(&mut self, data: &[u8], eof: bool, out: &mut dyn Write) -> Result<()> {
+        debug_assert!(self.buf.is_empty());
+        self.stats.in_bytes = data.len() as u64;
+        self.run(data, eof, out)
+    }
+
+    fn stats(&self) -> &Stats {
+        &self.stats
+    }
+}
+
+pub fn generate_delta(
+    sig: &mut dyn Read,
+    new_file: &mut dyn Read,
+    delta: &mut dyn Write,
+) -> Result<Stats> {
+    let sig = Signature::load(sig)?;
+    generate_delta_from_signature(&sig, new_file, delta)
+}
+
+pub fn generate_delta_from_signature(
+    sig: &Signature,
+    new_file: &mut dyn Read,
+    delta: &mut dyn Write,
+) -> Result<Stats> {
+    diff(scanner(sig), new_file, delta, &mut Monitor::new())
+}
+
+/// Like `generate_delta`, reporting to and checking `monitor` after each chunk
+/// of the new file.
+pub fn generate_delta_monitored(
+    sig: &mut dyn Read,
+    new_file: &mut dyn Read,
+    delta: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let sig = Signature::load(sig)?;
+    generate_delta_from_signature_monitored(&sig, new_file, delta, monitor)
+}
+
+/// Like `generate_delta_from_signature`, reporting to and checking `monitor`
+/// after each chunk of the new file.
+pub fn generate_delta_from_signature_monitored(
+    sig: &Signature,
+    new_file: &mut dyn Read,
+    delta: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    diff(scanner(sig), new_file, delta, monitor)
+}
+
+/// Like `generate_delta_from_signature`, with the strong sums computed by `H`, which
+/// must be the hash the signature's format names.
+pub fn generate_delta_with_hash<H: StrongHash + Send + 'static>(
+    sig: &Signature,
+    new_file: &mut dyn Read,
+    delta: &mut dyn Write,
+) -> Result<Stats> {
+    if !sig.magic().is_hash::<H>() {
+        return Err(Error::BadMagic(sig.magic() as u32));
+    }
+    if usize(sig.strong_len()) > H::MAX_LEN {
+        return Err(Error::InvalidStrongLen(sig.strong_len()));
+    }
+    if sig.key().is_some() {
+        return Err(Error::InvalidKey);
+    }
+    diff(
+        scanner_with(sig, H::new()),
+        new_file,
+        delta,
+        &mut Monitor::new(),
+    )
+}
+
+fn diff(
+    mut scanner: Box<dyn Scan + Send + '_>,
+    new_file: &mut dyn Read,
+    delta: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let delta = &mut Counted::new(BufWriter::new(delta));
+    write_u32be(delta, DELTA_MAGIC)?;
+
+    let mut buf = vec![0; INPUT_CHUNK_LEN];
+    loop {
+        let l = fill_buffer(new_file, &mut buf)?;
+        scanner.scan(&buf[..l], l < buf.len(), delta)?;
+        monitor.check(&Stats {
+            out_bytes: delta.bytes,
+            elapsed: start.elapsed(),
+            ..*scanner.stats()
+        })?;
+        if l < buf.len() {
+            break;
+        }
+    }
+    Command::End.write_to(delta)?;
+    delta.flush()?;
+    Ok(Stats {
+        out_bytes: delta.bytes,
+        elapsed: start.elapsed(),
+        ..*scanner.stats()
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checksum::Md4;
+    use crate::mksum::{generate_signature, SignatureFormat, SignatureOptions, KEY_LEN};
+    use crate::testutil::{pseudo_random, round_trip_with};
+    use std::io::Cursor;
+
+    fn delta_on_arrays(basis: &[u8], new: &[u8], block_len: u32) -> Vec<u8> {
+        let options = SignatureOptions {
+            block_len,
+            ..SignatureOptions::default()
+        };
+        delta_with_options(basis, new, &options)
+    }
+
+    fn delta_with_options(basis: &[u8], new: &[u8], options: &SignatureOptions) -> Vec<u8> {
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], options, &mut sig).unwrap();
+
+        let mut out_buf = Cursor::new(Vec::<u8>::new());
+        generate_delta(&mut &sig[..], &mut &new[..], &mut out_buf).unwrap();
+        out_buf.into_inner()
+    }
+
+    #[test]
+    pub fn command_encoding() {
+        let encode = |c: Command| {
+            let mut v = Vec::new();
+            c.write_to(&mut v).unwrap();
+            v
+        };
+        assert_eq!(encode(Command::End), [0x00]);
+        assert_eq!(encode(Command::Literal { len: 1 }), [0x01]);
+        assert_eq!(encode(Command::Literal { len: 64 }), [0x40]);
+        assert_eq!(encode(Command::Literal { len: 65 }), [0x41, 65]);
+        assert_eq!(encode(Command::Literal { len: 300 }), [0x42, 0x01, 0x2c]);
+        assert_eq!(
+            encode(Command::Copy {
+                offset: 0,
+                len: 2048
+            }),
+            [0x46, 0x00, 0x08, 0x00]
+        );
+        assert_eq!(
+            encode(Command::Copy {
+                offset: 1 << 32,
+                len: 1
+            }),
+            [0x51, 0, 0, 0, 1, 0, 0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    pub fn command_decoding() {
+        let decode = |v: &[u8]| Command::read_from(&mut &v[..]);
+        assert_eq!(decode(&[0x00]).unwrap(), Command::End);
+        assert_eq!(decode(&[0x40]).unwrap(), Command::Literal { len: 64 });
+        assert_eq!(
+            decode(&[0x43, 0, 1, 0, 0]).unwrap(),
+            Command::Literal { len: 1 << 16 }
+        );
+        assert_eq!(
+            decode(&[0x4a, 0x02, 0x00, 0x02, 0x00]).unwrap(),
+            Command::Copy {
+                offset: 512,
+                len: 512
+            }
+        );
+        assert!(matches!(
+            decode(&[0x55]).unwrap_err(),
+            Error::CorruptDelta("unknown command")
+        ));
+        assert!(matches!(
+            decode(&[0x46, 0x00, 0x08]).unwrap_err(),
+            Error::CorruptDelta("truncated command")
+        ));
+        assert!(matches!(
+            decode(&[]).unwrap_err(),
+            Error::CorruptDelta("missing end command")
+        ));
+    }
+
+    #[test]
+    pub fn command_decoding_in_pieces() {
+        let buf = [0x4a, 0x02, 0x00, 0x02, 0x00, 0xff];
+        for l in 0..5 {
+            assert_eq!(Command::decode(&buf[..l]).unwrap(), None);
+        }
+        assert_eq!(
+            Command::decode(&buf).unwrap(),
+            Some((
+                Command::Copy {
+                    offset: 512,
+                    len: 512
+                },
+                5
+            ))
+        );
+        assert_eq!(
+            Command::decode(&[0x07, 0xff]).unwrap(),
+            Some((Command::Literal { len: 7 }, 1))
+        );
+        assert!(Command::decode(&[0xff]).is_err());
+    }
+
+    #[test]
+    pub fn empty_new_file() {
+        let out_buf = delta_on_arrays(b"basis", &[], 4);
+        assert_eq!(out_buf, [b'r', b's', 0x02, 0x36, 0x00]);
+    }
+
+    #[test]
+    pub fn identical_files_are_one_copy() {
+        let basis: Vec<u8> = (0..10000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let out_buf = delta_on_arrays(&basis, &basis, 256);
+        // Full blocks and the short last block merge into a single copy.
+        assert_eq!(
+            out_buf,
+            [b'r', b's', 0x02, 0x36, 0x46, 0x00, 0x27, 0x10, 0x00]
+        );
+    }
+
+    #[test]
+    pub fn inserted_bytes_become_literal() {
+        let basis: Vec<u8> = (0..1024u32).map(|i| (i * 13 % 253) as u8).collect();
+        let mut new = basis[..512].to_vec();
+        new.extend_from_slice(b"xyz");
+        new.extend_from_slice(&basis[512..]);
+
+        let mut expected = vec![b'r', b's', 0x02, 0x36];
+        expected.extend_from_slice(&[0x46, 0x00, 0x02, 0x00]);
+        expected.extend_from_slice(&[0x03, b'x', b'y', b'z']);
+        expected.extend_from_slice(&[0x4a, 0x02, 0x00, 0x02, 0x00]);
+        expected.push(0x00);
+        for magic in [
+            SignatureFormat::Md4Sig,
+            SignatureFormat::Blake2Sig,
+            SignatureFormat::RkMd4Sig,
+            SignatureFormat::RkBlake2Sig,
+        ] {
+            let options = SignatureOptions {
+                magic,
+                block_len: 128,
+                strong_len: 8,
+                key: None,
+            };
+            assert_eq!(delta_with_options(&basis, &new, &options), expected);
+        }
+    }
+
+    #[test]
+    pub fn scan_in_place_matches_scan() {
+        let basis = pseudo_random(20_000, 21);
+        let mut new = basis.clone();
+        new.splice(5_000..5_000, pseudo_random(300, 22));
+        new.drain(12_000..12_500);
+        let options = SignatureOptions::default().with_block_len(256);
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+
+        let mut whole = scanner(&sig);
+        let mut expected = Vec::new();
+        whole.scan(&new, true, &mut expected).unwrap();
+        let stats = *whole.stats();
+
+        for step in [1, 7, 1000, new.len()] {
+            let mut scanner = scanner(&sig);
+            let mut out = Vec::new();
+            let mut end = 0;
+            while end < new.len() {
+                end = (end + step).min(new.len());
+                scanner
+                    .scan_in_place(&new[..end], end == new.len(), &mut out)
+                    .unwrap();
+            }
+            assert_eq!(out, expected, "step {}", step);
+            assert_eq!(*scanner.stats(), stats);
+        }
+    }
+
+    #[test]
+    pub fn bad_signature_magic() {
+        let err = generate_delta(&mut &[0u8; 12][..], &mut &b""[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0)));
+    }
+
+    #[test]
+    pub fn false_matches() {
+        // Both blocks have the same `Window` sum but different strong sums.
+        let options = SignatureOptions::default().with_block_len(3);
+        let mut sig = Vec::new();
+        generate_signature(&mut &[0u8, 2, 0][..], &options, &mut sig).unwrap();
+        let mut delta = Vec::new();
+        let stats = generate_delta(&mut &sig[..], &mut &[1u8, 0, 1][..], &mut delta).unwrap();
+        assert_eq!(stats.false_matches, 1);
+        assert_eq!((stats.lit_cmds, stats.copy_cmds), (1, 0));
+    }
+
+    #[test]
+    pub fn commands() {
+        let basis: Vec<u8> = (0..1024u32).map(|i| (i * 13 % 253) as u8).collect();
+        let mut new = basis[..512].to_vec();
+        new.extend_from_slice(b"xyz");
+        new.extend_from_slice(&basis[512..]);
+        let delta = delta_on_arrays(&basis, &new, 128);
+
+        let commands: Vec<Command> = Commands::new(&delta[..])
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(
+            commands,
+            [
+                Command::Copy {
+                    offset: 0,
+                    len: 512
+                },
+                Command::Literal { len: 3 },
+                Command::Copy {
+                    offset: 512,
+                    len: 512
+                },
+                Command::End,
+            ]
+        );
+        let text: Vec<String> = commands.iter().map(Command::to_string).collect();
+        assert_eq!(
+            text,
+            [
+                "COPY offset=0 len=512",
+                "LITERAL len=3",
+                "COPY offset=512 len=512",
+                "END"
+            ]
+        );
+
+        let mut truncated = Commands::new(&delta[..10]).unwrap();
+        assert!(truncated.next().unwrap().is_ok());
+        assert!(matches!(
+            truncated.next().unwrap().unwrap_err(),
+            Error::CorruptDelta("truncated literal data")
+        ));
+        assert!(truncated.next().is_none());
+
+        assert!(matches!(
+            Commands::new(&b"rs\x01\x36"[..]).err().unwrap(),
+            Error::BadMagic(0x72730136)
+        ));
+    }
+
+    #[test]
+    pub fn delta_with_hash_checks_magic() {
+        let basis = pseudo_random(20_000, 8);
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], &SignatureOptions::default(), &mut sig).unwrap();
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        let err =
+            generate_delta_with_hash::<Md4>(&sig, &mut &basis[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0x72730137)));
+    }
+
+    #[test]
+    pub fn keyed_round_trip() {
+        let basis = pseudo_random(20_000, 10);
+        let mut new = basis.clone();
+        new.splice(10_000..10_000, pseudo_random(100, 11));
+        for magic in [
+            SignatureFormat::KeyedBlake2Sig,
+            SignatureFormat::RkKeyedBlake2Sig,
+        ] {
+            let options = SignatureOptions::default()
+                .with_magic(magic)
+                .with_block_len(512)
+                .with_strong_len(8)
+                .with_key([0x5a; KEY_LEN]);
+            assert_eq!(round_trip_with(&basis, &new, &options), new);
+
+            // With the wrong key no block matches, so everything is sent as literals.
+            let mut sig = Vec::new();
+            generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+            sig[12] ^= 1;
+            let mut delta = Vec::new();
+            generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+            assert!(delta.len() > new.len());
+        }
+    }
+}
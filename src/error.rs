@@ -0,0 +1,76 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    /// The input does not start with a known signature or delta magic number.
+    BadMagic(u32),
+
+    InvalidBlockLen(u32),
+
+    InvalidStrongLen(u32),
+
+    /// A keyed signature format without a key, or a key where it cannot be used.
+    InvalidKey,
+
+    CorruptSignature(&'static str),
+
+    CorruptDelta(&'static str),
+
+    /// The operation was stopped through its `progress::CancelToken`.
+    Cancelled,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Maps an unexpected end of input to `corrupt`, keeping other I/O errors.
+    pub(crate) fn truncated(e: io::Error, corrupt: Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            corrupt
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::BadMagic(magic) => write!(f, "bad magic number {:#010x}", magic),
+            Error::InvalidBlockLen(l) => write!(f, "invalid block length {}", l),
+            Error::InvalidStrongLen(l) => write!(f, "invalid strong sum length {}", l),
+            Error::InvalidKey => write!(f, "signature key does not match the format"),
+            Error::CorruptSignature(msg) => write!(f, "corrupt signature: {}", msg),
+            Error::CorruptDelta(msg) => write!(f, "corrupt delta: {}", msg),
+            Error::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::mem;
+use std::time::Instant;
+
+use cast::usize;
+
+use crate::delta::{command_len, scanner, Command, Scan, DELTA_MAGIC, INPUT_CHUNK_LEN};
+use crate::error::{Error, Result};
+use crate::mksum::{write_header, BlockSums, SignatureOptions};
+use crate::progress::Monitor;
+use crate::stats::Stats;
+use crate::sumset::Signature;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    /// The job needs more input or more room for output.
+    Blocked,
+
+    /// All output has been produced and handed out.
+    Done,
+}
+
+/// The caller's input and output windows, advanced past whatever `Job::iter`
+/// consumes and produces, like librsync's `rs_buffers_t`.
+#[derive(Debug)]
+pub struct Buffers<'a> {
+    pub next_in: &'a [u8],
+
+    /// Set once `next_in` holds the last of the input.
+    pub eof_in: bool,
+
+    pub next_out: &'a mut [u8],
+}
+
+impl<'a> Buffers<'a> {
+    pub fn new(next_in: &'a [u8], eof_in: bool, next_out: &'a mut [u8]) -> Buffers<'a> {
+        Buffers {
+            next_in,
+            eof_in,
+            next_out,
+        }
+    }
+
+    fn take_in(&mut self, max: usize) -> &'a [u8] {
+        let (taken, rest) = self.next_in.split_at(max.min(self.next_in.len()));
+        self.next_in = rest;
+        taken
+    }
+
+    fn put_out(&mut self, data: &[u8]) -> usize {
+        let out = mem::take(&mut self.next_out);
+        let n = data.len().min(out.len());
+        let (filled, rest) = out.split_at_mut(n);
+        filled.copy_from_slice(&data[..n]);
+        self.next_out = rest;
+        n
+    }
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+enum PatchState {
+    Header,
+    Command,
+    Literal(u64),
+    Copy { offset: u64, len: u64 },
+    End,
+}
+
+enum Kind<'s> {
+    Signature {
+        block_len: usize,
+        block_sums: BlockSums,
+        block: Vec<u8>,
+    },
+    Delta {
+        scanner: Box<dyn Scan + 's>,
+    },
+    Patch {
+        basis: Box<dyn ReadSeek + 's>,
+        /// Bytes of a header or command that has not fully arrived yet.
+        pending: Vec<u8>,
+        state: PatchState,
+    },
+}
+
+/// An rdiff operation driven by pushing input and pulling output, for callers
+/// that cannot block on a `Read` or `Write`.
+pub struct Job<'s> {
+    kind: Kind<'s>,
+
+    /// Output produced but not yet handed to the caller.
+    out: Vec<u8>,
+
+    out_pos: usize,
+
+    finished: bool,
+
+    start: Instant,
+
+    /// Counts kept by the job itself; a delta's commands are counted by its scanner.
+    stats: Stats,
+}
+
+impl<'s> Job<'s> {
+    fn new(kind: Kind<'s>) -> Job<'s> {
+        Job {
+            kind,
+            out: Vec::new(),
+            out_pos: 0,
+            finished: false,
+            start: Instant::now(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Reads a basis and writes its signature.
+    pub fn signature(options: &SignatureOptions) -> Result<Job<'static>> {
+        options.validate()?;
+        let mut job = Job::new(Kind::Signature {
+            block_len: usize(options.block_len),
+            block_sums: options.block_sums(),
+            block: Vec::with_capacity(usize(options.block_len)),
+        });
+        write_header(options, &mut job.out)?;
+        Ok(job)
+    }
+
+    /// Reads a new file and writes its delta against `sig`.
+    pub fn delta(sig: &'s Signature) -> Job<'s> {
+        let mut job = Job::new(Kind::Delta {
+            scanner: scanner(sig),
+        });
+        job.out.extend_from_slice(&DELTA_MAGIC.to_be_bytes());
+        job
+    }
+
+    /// Reads a delta and writes the new file, copying from `basis` as needed.
+    pub fn patch<B: Read + Seek + 's>(basis: B) -> Job<'s> {
+        Job::new(Kind::Patch {
+            basis: Box::new(basis),
+            pending: Vec::new(),
+            state: PatchState::Header,
+        })
+    }
+
+    /// What the job has done so far, like librsync's `rs_job_statistics`.
+    pub fn statistics(&self) -> Stats {
+        match &self.kind {
+            Kind::Delta { scanner } => Stats {
+                in_bytes: self.stats.in_bytes,
+                out_bytes: self.stats.out_bytes,
+                elapsed: self.stats.elapsed,
+                ..*scanner.stats()
+            },
+            _ => self.stats,
+        }
+    }
+
+    /// Consumes as much input and fills as much output as possible.
+    pub fn iter(&mut self, buffers: &mut Buffers) -> Result<JobStatus> {
+        self.iter_monitored(buffers, &mut Monitor::new())
+    }
+
+    /// Like `iter`, then reports to and checks `monitor`.
+    pub fn iter_monitored(
+        &mut self,
+        buffers: &mut Buffers,
+        monitor: &mut Monitor,
+    ) -> Result<JobStatus> {
+        let avail_in = buffers.next_in.len();
+        let avail_out = buffers.next_out.len();
+        let status = self.iter_counted(buffers);
+        self.stats.in_bytes += (avail_in - buffers.next_in.len()) as u64;
+        self.stats.out_bytes += (avail_out - buffers.next_out.len()) as u64;
+        if !self.finished {
+            self.stats.elapsed = self.start.elapsed();
+        }
+        let status = status?;
+        monitor.check(&self.statistics())?;
+        Ok(status)
+    }
+
+    fn iter_counted(&mut self, buffers: &mut Buffers) -> Result<JobStatus> {
+        loop {
+            self.out_pos += buffers.put_out(&self.out[self.out_pos..]);
+            if self.out_pos < self.out.len() {
+                return Ok(JobStatus::Blocked);
+            }
+            self.out.clear();
+            self.out_pos = 0;
+            if self.finished {
+                return Ok(JobStatus::Done);
+            }
+
+            let avail_in = buffers.next_in.len();
+            self.step(buffers)?;
+            if buffers.next_in.len() == avail_in && self.out.is_empty() && !self.finished {
+                return Ok(JobStatus::Blocked);
+            }
+        }
+    }
+
+    /// Makes a bounded amount of progress into `self.out`.
+    fn step(&mut self, buffers: &mut Buffers) -> Result<()> {
+        let out = &mut self.out;
+        match &mut self.kind {
+            Kind::Signature {
+                block_len,
+                block_sums,
+                block,
+            } => {
+                let want = *block_len - block.len();
+                block.extend_from_slice(buffers.take_in(want));
+                let at_end = buffers.eof_in && buffers.next_in.is_empty();
+                if block.len() == *block_len || (at_end && !block.is_empty()) {
+                    block_sums(block, out)?;
+                    self.stats.blocks += 1;
+                    block.clear();
+                } else if at_end {
+                    self.finished = true;
+                }
+            }
+            Kind::Delta { scanner } => {
+                let data = buffers.take_in(INPUT_CHUNK_LEN);
+                if buffers.eof_in && buffers.next_in.is_empty() {
+                    scanner.scan(data, true, out)?;
+                    Command::End.write_to(out)?;
+                    self.finished = true;
+                } else {
+                    scanner.scan(data, false, out)?;
+                }
+            }
+            Kind::Patch {
+                basis,
+                pending,
+                state,
+            } => {
+                self.finished = patch_step(basis, pending, state, buffers, out, &mut self.stats)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Moves bytes from `buffers` into `pending` until it holds `want` of them.
+fn gather(pending: &mut Vec<u8>, want: usize, buffers: &mut Buffers) {
+    if pending.len() < want {
+        let taken = buffers.take_in(want - pending.len());
+        pending.extend_from_slice(taken);
+    }
+}
+
+fn patch_step(
+    basis: &mut Box<dyn ReadSeek + '_>,
+    pending: &mut Vec<u8>,
+    state: &mut PatchState,
+    buffers: &mut Buffers,
+    out: &mut Vec<u8>,
+    stats: &mut Stats,
+) -> Result<bool> {
+    let at_end = buffers.eof_in && buffers.next_in.is_empty();
+    match *state {
+        PatchState::Header => {
+            gather(pending, 4, buffers);
+            if pending.len() == 4 {
+                let magic = u32::from_be_bytes([pending[0], pending[1], pending[2], pending[3]]);
+                if magic != DELTA_MAGIC {
+                    return Err(Error::BadMagic(magic));
+                }
+                pending.clear();
+                *state = PatchState::Command;
+            } else if at_end {
+                return Err(Error::CorruptDelta("truncated header"));
+            }
+        }
+        PatchState::Command => {
+            gather(pending, 1, buffers);
+            if let Some(&op) = pending.first() {
+                gather(pending, command_len(op), buffers);
+            }
+            match Command::decode(pending)? {
+                Some((c, _)) => {
+                    pending.clear();
+                    stats.count_command(&c);
+                    *state = match c {
+                        Command::End => PatchState::End,
+                        Command::Literal { len: 0 } | Command::Copy { len: 0, .. } => {
+                            PatchState::Command
+                        }
+                        Command::Literal { len } => PatchState::Literal(len),
+                        Command::Copy { offset, len } => PatchState::Copy { offset, len },
+                    };
+                }
+                None if at_end => {
+                    return Err(Error::CorruptDelta(if pending.is_empty() {
+                        "missing end command"
+                    } else {
+                        "truncated command"
+                    }));
+                }
+                None => {}
+            }
+        }
+        PatchState::Literal(len) => {
+            let data = buffers.take_in(len.min(INPUT_CHUNK_LEN as u64) as usize);
+            out.extend_from_slice(data);
+            let left = len - data.len() as u64;
+            if left == 0 {
+                *state = PatchState::Command;
+            } else if at_end {
+                return Err(Error::CorruptDelta("truncated literal data"));
+            } else {
+                *state = PatchState::Literal(left);
+            }
+        }
+        PatchState::Copy { offset, len } => {
+            let chunk = len.min(INPUT_CHUNK_LEN as u64);
+            basis.seek(SeekFrom::Start(offset))?;
+            let copied = basis.take(chunk).read_to_end(out)? as u64;
+            if copied < chunk {
+                return Err(Error::CorruptDelta("copy past the end of the basis"));
+            }
+            *state = if chunk == len {
+                PatchState::Command
+            } else {
+                PatchState::Copy {
+                    offset: offset + chunk,
+                    len: len - chunk,
+                }
+            };
+        }
+        PatchState::End => return Ok(true),
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::delta::generate_delta;
+    use crate::mksum::{generate_signature, SignatureFormat};
+    use crate::patch::apply_patch;
+    use crate::progress::CancelToken;
+    use crate::testutil::{pseudo_random, without_elapsed};
+    use std::io::Cursor;
+
+    /// Feeds `input` in pieces of `in_chunk` bytes through output windows of `out_chunk` bytes.
+    fn run_job(job: &mut Job, input: &[u8], in_chunk: usize, out_chunk: usize) -> Result<Vec<u8>> {
+        let mut result = Vec::new();
+        let mut out_buf = vec![0u8; out_chunk];
+        let mut fed = 0;
+        loop {
+            let end = (fed + in_chunk).min(input.len());
+            let mut buffers = Buffers::new(&input[fed..end], end == input.len(), &mut out_buf);
+            let status = job.iter(&mut buffers)?;
+            let consumed = end - fed - buffers.next_in.len();
+            let produced = out_chunk - buffers.next_out.len();
+            fed += consumed;
+            result.extend_from_slice(&out_buf[..produced]);
+            if status == JobStatus::Done {
+                return Ok(result);
+            }
+            assert!(consumed > 0 || produced > 0 || end < input.len());
+        }
+    }
+
+    fn inputs() -> (Vec<u8>, Vec<u8>, SignatureOptions) {
+        let basis = pseudo_random(30_000, 1);
+        let mut new = basis.clone();
+        new.splice(12_000..12_000, pseudo_random(500, 2));
+        new.drain(20_000..21_000);
+        let options = SignatureOptions {
+            magic: SignatureFormat::RkBlake2Sig,
+            block_len: 700,
+            strong_len: 12,
+            key: None,
+        };
+        (basis, new, options)
+    }
+
+    #[test]
+    pub fn signature_job() {
+        let (basis, _, options) = inputs();
+        let mut expected = Vec::new();
+        let stats = generate_signature(&mut &basis[..], &options, &mut expected).unwrap();
+
+        for (in_chunk, out_chunk) in [(1, 1), (7, 3), (700, 16), (100_000, 100_000)] {
+            let mut job = Job::signature(&options).unwrap();
+            let sig = run_job(&mut job, &basis, in_chunk, out_chunk).unwrap();
+            assert_eq!(sig, expected);
+            assert_eq!(without_elapsed(job.statistics()), without_elapsed(stats));
+        }
+
+        let mut job = Job::signature(&options).unwrap();
+        assert_eq!(run_job(&mut job, &[], 1, 1).unwrap(), &expected[..12]);
+        assert!(Job::signature(&options.with_block_len(0)).is_err());
+    }
+
+    #[test]
+    pub fn delta_job() {
+        let (basis, new, options) = inputs();
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        let mut expected = Vec::new();
+        let stats = generate_delta(&mut &sig[..], &mut &new[..], &mut expected).unwrap();
+
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        for (in_chunk, out_chunk) in [(1, 1), (13, 5), (100_000, 100_000)] {
+            let mut job = Job::delta(&sig);
+            let delta = run_job(&mut job, &new, in_chunk, out_chunk).unwrap();
+            assert_eq!(delta, expected);
+            assert_eq!(without_elapsed(job.statistics()), without_elapsed(stats));
+        }
+    }
+
+    #[test]
+    pub fn patch_job() {
+        let (basis, new, options) = inputs();
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        let mut delta = Vec::new();
+        generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+
+        let mut expected = Vec::new();
+        let stats = apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut expected).unwrap();
+        assert_eq!(expected, new);
+
+        for (in_chunk, out_chunk) in [(1, 1), (3, 1000), (100_000, 7), (100_000, 100_000)] {
+            let mut job = Job::patch(Cursor::new(&basis));
+            let out = run_job(&mut job, &delta, in_chunk, out_chunk).unwrap();
+            assert_eq!(out, new);
+            assert_eq!(without_elapsed(job.statistics()), without_elapsed(stats));
+        }
+    }
+
+    #[test]
+    pub fn monitored_job() {
+        let (basis, _, options) = inputs();
+        let mut job = Job::signature(&options).unwrap();
+        let mut seen = Vec::new();
+        let mut observer = |stats: &Stats| seen.push(stats.in_bytes);
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut out = vec![0; 100_000];
+        for chunk in basis.chunks(10_000) {
+            let mut buffers = Buffers::new(chunk, false, &mut out);
+            job.iter_monitored(&mut buffers, &mut monitor).unwrap();
+        }
+        assert_eq!(seen, [10_000, 20_000, 30_000]);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut monitor = Monitor::new().with_cancel(&cancel);
+        let mut buffers = Buffers::new(&basis, true, &mut out);
+        let err = job.iter_monitored(&mut buffers, &mut monitor).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+    }
+
+    #[test]
+    pub fn patch_job_errors() {
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let err = run_job(&mut job, b"rs\x02", 1, 1).unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("truncated header")));
+
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let err = run_job(&mut job, b"rs\x01\x36", 1, 1).unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0x72730136)));
+
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let err = run_job(&mut job, b"rs\x02\x36\x45\x00", 1, 1).unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("truncated command")));
+
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let err = run_job(&mut job, b"rs\x02\x36\x02x", 1, 1).unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("truncated literal data")));
+
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let err = run_job(&mut job, b"rs\x02\x36\x45\x02\x04\x00", 1, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CorruptDelta("copy past the end of the basis")
+        ));
+
+        // Empty commands produce nothing but must not stall the job.
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let delta = b"rs\x02\x36\x41\x00\x45\x01\x00\x45\x01\x02\x00";
+        assert_eq!(run_job(&mut job, delta, 100, 100).unwrap(), b"as");
+
+        let mut job = Job::patch(Cursor::new(b"basis"));
+        let err = run_job(&mut job, b"rs\x02\x36", 1, 1).unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("missing end command")));
+    }
+}
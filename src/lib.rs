@@ -1,4 +1,26 @@
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod checksum;
+pub mod delta;
+mod error;
+pub mod job;
 pub mod mksum;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+pub mod patch;
+pub mod progress;
+pub mod rabinkarp;
 pub mod rollsum;
+mod stats;
+pub mod sumset;
+#[cfg(test)]
+mod testutil;
+
+pub use crate::error::{Error, Result};
+pub use crate::stats::Stats;
 
 pub const DEFAULT_BLOCK_LEN: u32 = 2048;
+
+/// Longest block a signature may have. Both rollsums handle any length, but signature
+/// and delta each buffer a whole block, so an untrusted signature must not pick it.
+pub const MAX_BLOCK_LEN: u32 = 1 << 28;
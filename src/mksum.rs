@@ -1,20 +1,190 @@
-use std::io::{BufWriter, Read, Result, Write};
+use std::any::TypeId;
+use std::io::{self, BufWriter, Read, Write};
+use std::marker::PhantomData;
+use std::time::Instant;
 
-use blake2::digest::{Update, VariableOutput};
-use blake2::Blake2bVar;
 use byteorder::{BigEndian, WriteBytesExt};
 use cast::usize;
 
+use crate::checksum::{Blake2, Md4, StrongHash};
+use crate::error::{Error, Result};
+use crate::progress::Monitor;
+use crate::rabinkarp::RabinKarp;
 use crate::rollsum::Window;
+use crate::stats::{Counted, Stats};
 
 use super::rollsum::Rollsum;
 
-#[derive(Debug, Copy, Clone)]
+/// Which weak and strong sums a signature holds. Some formats only exist with
+/// the feature for their hash, so matches outside the crate need a wildcard arm.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum SignatureFormat {
+    Md4Sig = 0x72730136,
     Blake2Sig = 0x72730137,
+    RkMd4Sig = 0x72730146,
+    RkBlake2Sig = 0x72730147,
+    /// Keyed BLAKE2b strong sums, with the key stored after the header. Not
+    /// understood by librsync.
+    KeyedBlake2Sig = 0x72730138,
+    /// `KeyedBlake2Sig` with `RabinKarp` weak sums. Not understood by librsync.
+    RkKeyedBlake2Sig = 0x72730148,
+    /// BLAKE3 strong sums. Not understood by librsync.
+    #[cfg(feature = "blake3")]
+    Blake3Sig = 0x72730139,
+    /// `Blake3Sig` with `RabinKarp` weak sums. Not understood by librsync.
+    #[cfg(feature = "blake3")]
+    RkBlake3Sig = 0x72730149,
+    /// SHA-256 strong sums. Not understood by librsync.
+    #[cfg(feature = "sha2")]
+    Sha256Sig = 0x7273013a,
+    /// `Sha256Sig` with `RabinKarp` weak sums. Not understood by librsync.
+    #[cfg(feature = "sha2")]
+    RkSha256Sig = 0x7273014a,
+}
+
+pub(crate) const RS_MAX_STRONG_SUM_LENGTH: usize = 32;
+
+/// Length of the key of the keyed signature formats.
+pub const KEY_LEN: usize = 16;
+
+/// Writes the weak and strong sums of one block.
+pub(crate) type BlockSums = Box<dyn Fn(&[u8], &mut dyn Write) -> io::Result<()> + Send + Sync>;
+
+/// Something done with whichever strong hash a signature format names; see
+/// `SignatureFormat::use_hash`.
+pub(crate) trait UseHash {
+    type Output;
+
+    fn with<H: StrongHash + Send + Sync + 'static>(self, hash: H) -> Self::Output;
+}
+
+struct MaxLen;
+
+impl UseHash for MaxLen {
+    type Output = u32;
+
+    fn with<H: StrongHash + Send + Sync + 'static>(self, _: H) -> u32 {
+        H::MAX_LEN as u32
+    }
 }
 
-const RS_MAX_STRONG_SUM_LENGTH: usize = 32;
+struct IsHash<H>(PhantomData<H>);
+
+impl<H: 'static> UseHash for IsHash<H> {
+    type Output = bool;
+
+    fn with<G: StrongHash + Send + Sync + 'static>(self, _: G) -> bool {
+        TypeId::of::<G>() == TypeId::of::<H>()
+    }
+}
+
+struct MakeBlockSums<'a>(&'a SignatureOptions);
+
+impl UseHash for MakeBlockSums<'_> {
+    type Output = BlockSums;
+
+    fn with<H: StrongHash + Send + Sync + 'static>(self, hash: H) -> BlockSums {
+        block_sums_with(self.0, hash)
+    }
+}
+
+impl SignatureFormat {
+    pub fn from_magic(magic: u32) -> Option<SignatureFormat> {
+        match magic {
+            0x72730136 => Some(SignatureFormat::Md4Sig),
+            0x72730137 => Some(SignatureFormat::Blake2Sig),
+            0x72730146 => Some(SignatureFormat::RkMd4Sig),
+            0x72730147 => Some(SignatureFormat::RkBlake2Sig),
+            0x72730138 => Some(SignatureFormat::KeyedBlake2Sig),
+            0x72730148 => Some(SignatureFormat::RkKeyedBlake2Sig),
+            #[cfg(feature = "blake3")]
+            0x72730139 => Some(SignatureFormat::Blake3Sig),
+            #[cfg(feature = "blake3")]
+            0x72730149 => Some(SignatureFormat::RkBlake3Sig),
+            #[cfg(feature = "sha2")]
+            0x7273013a => Some(SignatureFormat::Sha256Sig),
+            #[cfg(feature = "sha2")]
+            0x7273014a => Some(SignatureFormat::RkSha256Sig),
+            _ => None,
+        }
+    }
+
+    /// Whether the weak sum is `RabinKarp` rather than `rollsum::Window`.
+    pub fn is_rabinkarp(self) -> bool {
+        // Each RabinKarp format's magic is its `Window` counterpart's plus 0x10.
+        self as u32 & 0xf0 == 0x40
+    }
+
+    /// Whether librsync understands signatures in this format.
+    pub fn is_librsync(self) -> bool {
+        matches!(
+            self,
+            SignatureFormat::Md4Sig
+                | SignatureFormat::Blake2Sig
+                | SignatureFormat::RkMd4Sig
+                | SignatureFormat::RkBlake2Sig
+        )
+    }
+
+    /// Whether the strong sums are keyed, so the signature carries a key.
+    pub fn is_keyed(self) -> bool {
+        matches!(
+            self,
+            SignatureFormat::KeyedBlake2Sig | SignatureFormat::RkKeyedBlake2Sig
+        )
+    }
+
+    /// Calls `f` with the strong hash this format names, keyed with `key` if the
+    /// format is keyed. The one place that maps formats to hashes.
+    pub(crate) fn use_hash<F: UseHash>(self, key: Option<&[u8; KEY_LEN]>, f: F) -> F::Output {
+        match self {
+            SignatureFormat::Md4Sig | SignatureFormat::RkMd4Sig => f.with(Md4::new()),
+            SignatureFormat::Blake2Sig
+            | SignatureFormat::RkBlake2Sig
+            | SignatureFormat::KeyedBlake2Sig
+            | SignatureFormat::RkKeyedBlake2Sig => f.with(blake2(key)),
+            #[cfg(feature = "blake3")]
+            SignatureFormat::Blake3Sig | SignatureFormat::RkBlake3Sig => {
+                f.with(crate::checksum::Blake3::new())
+            }
+            #[cfg(feature = "sha2")]
+            SignatureFormat::Sha256Sig | SignatureFormat::RkSha256Sig => {
+                f.with(crate::checksum::Sha256::new())
+            }
+        }
+    }
+
+    /// Whether `H` is the strong hash this format names.
+    pub(crate) fn is_hash<H: 'static>(self) -> bool {
+        self.use_hash(None, IsHash::<H>(PhantomData))
+    }
+
+    pub(crate) fn weak_sum(self, buf: &[u8]) -> u32 {
+        if self.is_rabinkarp() {
+            let mut rs = RabinKarp::new();
+            rs.update(buf);
+            rs.digest()
+        } else {
+            let mut rs = Window::new();
+            rs.update(buf);
+            rs.digest()
+        }
+    }
+
+    /// Length of the full strong sum; signatures may store a truncated prefix of it.
+    pub fn max_strong_len(self) -> u32 {
+        self.use_hash(None, MaxLen)
+    }
+}
+
+/// The BLAKE2b hash for a signature with `key`, if it has one.
+fn blake2(key: Option<&[u8; KEY_LEN]>) -> Blake2 {
+    match key {
+        Some(key) => Blake2::keyed(key),
+        None => Blake2::new(),
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 pub struct SignatureOptions {
@@ -23,30 +193,101 @@ pub struct SignatureOptions {
     pub block_len: u32,
 
     pub strong_len: u32,
+
+    /// Key for the keyed formats, which should be chosen at random for each signature
+    /// so that nobody can prepare a file whose blocks collide with the basis.
+    pub key: Option<[u8; KEY_LEN]>,
 }
 
 impl SignatureOptions {
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> SignatureOptions {
         SignatureOptions {
             magic: SignatureFormat::Blake2Sig,
             block_len: super::DEFAULT_BLOCK_LEN,
             strong_len: RS_MAX_STRONG_SUM_LENGTH as u32,
+            key: None,
         }
     }
 
+    pub fn with_magic(self, magic: SignatureFormat) -> SignatureOptions {
+        SignatureOptions { magic, ..self }
+    }
+
+    pub fn with_block_len(self, block_len: u32) -> SignatureOptions {
+        SignatureOptions { block_len, ..self }
+    }
+
     pub fn with_strong_len(self, s: u32) -> SignatureOptions {
         SignatureOptions {
             strong_len: s,
             ..self
         }
     }
+
+    /// Sets the key; the format must also be changed to a keyed one.
+    pub fn with_key(self, key: [u8; KEY_LEN]) -> SignatureOptions {
+        SignatureOptions {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    /// Picks lengths for a basis of `file_len` bytes the way librsync's `rs_sig_args` does:
+    /// blocks of about `sqrt(file_len)` bytes and the shortest strong sum that still makes
+    /// a false match unlikely.
+    pub fn recommended(file_len: u64, magic: SignatureFormat) -> SignatureOptions {
+        let block_len = if file_len < 256 * 256 {
+            256
+        } else {
+            // Round down to a multiple of the BLAKE2b (and so MD4) block size.
+            (file_len.isqrt() & !127).min(super::MAX_BLOCK_LEN as u64) as u32
+        };
+        SignatureOptions {
+            magic,
+            block_len,
+            strong_len: min_strong_len(file_len, block_len).min(magic.max_strong_len()),
+            key: None,
+        }
+    }
+
+    /// Checks the lengths against the limits of the chosen format, and that there is
+    /// a key exactly when the format is keyed.
+    pub fn validate(&self) -> Result<()> {
+        if self.block_len == 0 || self.block_len > super::MAX_BLOCK_LEN {
+            return Err(Error::InvalidBlockLen(self.block_len));
+        }
+        if self.strong_len == 0 || self.strong_len > self.magic.max_strong_len() {
+            return Err(Error::InvalidStrongLen(self.strong_len));
+        }
+        if self.key.is_some() != self.magic.is_keyed() {
+            return Err(Error::InvalidKey);
+        }
+        Ok(())
+    }
+
+    /// The sums writer for the strong hash (and key) this format names.
+    pub(crate) fn block_sums(&self) -> BlockSums {
+        self.magic.use_hash(self.key.as_ref(), MakeBlockSums(self))
+    }
+}
+
+/// The shortest strong sum that makes a false match unlikely for a basis of
+/// `file_len` bytes in blocks of `block_len`, as librsync's `rs_sig_args` works it out.
+pub fn min_strong_len(file_len: u64, block_len: u32) -> u32 {
+    2 + (ln2(file_len.saturating_add(1 << 24)) + ln2(file_len / block_len as u64 + 1)).div_ceil(8)
 }
 
-fn write_u32be(f: &mut dyn Write, a: u32) -> Result<()> {
+fn ln2(v: u64) -> u32 {
+    v.checked_ilog2().unwrap_or(0)
+}
+
+pub(crate) fn write_u32be(f: &mut dyn Write, a: u32) -> io::Result<()> {
     f.write_u32::<BigEndian>(a)
 }
 
-fn fill_buffer(inf: &mut dyn Read, buf: &mut [u8]) -> Result<usize> {
+#[allow(clippy::needless_return)]
+pub(crate) fn fill_buffer(inf: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
     let mut bytes_read: usize = 0;
     while bytes_read < buf.len() {
         let l = inf.read(&mut buf[bytes_read..])?;
@@ -59,49 +300,183 @@ fn fill_buffer(inf: &mut dyn Read, buf: &mut [u8]) -> Result<usize> {
     return Ok(bytes_read);
 }
 
+pub(crate) fn write_header(options: &SignatureOptions, sig: &mut dyn Write) -> io::Result<()> {
+    write_u32be(sig, options.magic as u32)?;
+    write_u32be(sig, options.block_len)?;
+    write_u32be(sig, options.strong_len)?;
+    if let Some(key) = &options.key {
+        sig.write_all(key)?;
+    }
+    Ok(())
+}
+
+/// `hash` is cloned for each block, so it may carry a key.
+fn block_sums_with<H: StrongHash + Send + Sync + 'static>(
+    options: &SignatureOptions,
+    hash: H,
+) -> BlockSums {
+    let magic = options.magic;
+    let strong_len = usize(options.strong_len);
+    Box::new(move |block, sig| {
+        write_u32be(sig, magic.weak_sum(block))?;
+        let mut d = [0u8; RS_MAX_STRONG_SUM_LENGTH];
+        let d = &mut d[..strong_len];
+        let mut h = hash.clone();
+        h.update(block);
+        h.finalize_into(d);
+        sig.write_all(d)
+    })
+}
+
 pub fn generate_signature(
     basis: &mut dyn Read,
     options: &SignatureOptions,
     sig: &mut dyn Write,
-) -> Result<()> {
+) -> Result<Stats> {
+    generate_signature_monitored(basis, options, sig, &mut Monitor::new())
+}
+
+/// Like `generate_signature`, reporting to and checking `monitor` after each block.
+pub fn generate_signature_monitored(
+    basis: &mut dyn Read,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    options.validate()?;
+    sign(basis, options, sig, options.block_sums(), monitor)
+}
+
+/// Like `generate_signature`, with the strong sums computed by `H`, which must be
+/// the hash `options.magic` names so that the header describes the sums.
+pub fn generate_signature_with_hash<H: StrongHash + Send + Sync + 'static>(
+    basis: &mut dyn Read,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+) -> Result<Stats> {
+    options.validate()?;
+    if !options.magic.is_hash::<H>() {
+        return Err(Error::BadMagic(options.magic as u32));
+    }
+    if usize(options.strong_len) > H::MAX_LEN {
+        return Err(Error::InvalidStrongLen(options.strong_len));
+    }
+    if options.key.is_some() {
+        return Err(Error::InvalidKey);
+    }
+    sign(
+        basis,
+        options,
+        sig,
+        block_sums_with(options, H::new()),
+        &mut Monitor::new(),
+    )
+}
+
+fn sign(
+    basis: &mut dyn Read,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+    block_sums: BlockSums,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let mut stats = Stats::default();
     let mut buf = vec![0; usize(options.block_len)];
 
-    let sig = &mut BufWriter::new(sig);
-    write_u32be(sig, options.magic as u32)?;
-    write_u32be(sig, options.block_len)?;
-    write_u32be(sig, options.strong_len)?;
+    let sig = &mut Counted::new(BufWriter::new(sig));
+    write_header(options, sig)?;
 
     loop {
         let l = fill_buffer(basis, &mut buf)?;
         if l == 0 {
             break;
         }
-        let b = &buf[..l];
-        {
-            let mut rs = Window::new();
-            rs.update(b);
-            write_u32be(sig, rs.digest())?;
-        }
-        {
-            let mut hasher = Blake2bVar::new(32).unwrap();
-            hasher.update(b);
-            let mut d = [0u8; RS_MAX_STRONG_SUM_LENGTH];
-            hasher.finalize_variable(&mut d).unwrap();
-            sig.write(&d[..(options.strong_len as usize)])?;
+        stats.in_bytes += l as u64;
+        stats.blocks += 1;
+        block_sums(&buf[..l], sig)?;
+        stats.out_bytes = sig.bytes;
+        stats.elapsed = start.elapsed();
+        monitor.check(&stats)?;
+        if l < buf.len() {
+            break;
         }
+    }
+    sig.flush()?;
+    stats.out_bytes = sig.bytes;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+/// Bytes of basis read ahead and hashed at once by `generate_signature_parallel`.
+#[cfg(feature = "rayon")]
+const PARALLEL_BATCH_LEN: usize = 8 << 20;
+
+/// Like `generate_signature`, but hashes batches of blocks on the rayon thread
+/// pool. The output is identical.
+#[cfg(feature = "rayon")]
+pub fn generate_signature_parallel(
+    basis: &mut dyn Read,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+) -> Result<Stats> {
+    generate_signature_parallel_monitored(basis, options, sig, &mut Monitor::new())
+}
+
+/// Like `generate_signature_parallel`, reporting to and checking `monitor` after
+/// each batch of blocks.
+#[cfg(feature = "rayon")]
+pub fn generate_signature_parallel_monitored(
+    basis: &mut dyn Read,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    use rayon::prelude::*;
+
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    options.validate()?;
+    let block_sums = options.block_sums();
+    let block_len = usize(options.block_len);
+    let entry_len = 4 + usize(options.strong_len);
+    let blocks_per_batch = (PARALLEL_BATCH_LEN / block_len).max(1);
+    let mut buf = vec![0; blocks_per_batch * block_len];
+    let mut out = Vec::new();
+
+    let sig = &mut Counted::new(BufWriter::new(sig));
+    write_header(options, sig)?;
+
+    loop {
+        let l = fill_buffer(basis, &mut buf)?;
+        stats.in_bytes += l as u64;
+        stats.blocks += l.div_ceil(block_len) as u64;
+        out.resize(l.div_ceil(block_len) * entry_len, 0);
+        out.par_chunks_mut(entry_len)
+            .zip(buf[..l].par_chunks(block_len))
+            .try_for_each(|(mut entry, block)| block_sums(block, &mut entry))?;
+        sig.write_all(&out)?;
+        stats.out_bytes = sig.bytes;
+        stats.elapsed = start.elapsed();
+        monitor.check(&stats)?;
         if l < buf.len() {
             break;
         }
     }
-    Ok(())
+    sig.flush()?;
+    stats.out_bytes = sig.bytes;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::testutil::pseudo_random;
     use std::io::Cursor;
     use std::vec::Vec;
 
+    #[allow(clippy::useless_asref)]
     fn generate_signature_on_arrays(in_buf: &[u8]) -> Vec<u8> {
         let mut out_buf = Cursor::new(Vec::<u8>::new());
         let options = SignatureOptions::default();
@@ -126,4 +501,247 @@ mod test {
 
         assert_eq!(out_buf.len(), 12 + 4 + 32);
     }
+
+    #[test]
+    pub fn rabinkarp_signature() {
+        let options = SignatureOptions {
+            magic: SignatureFormat::RkBlake2Sig,
+            ..SignatureOptions::default()
+        };
+        let mut out_buf = Vec::new();
+        generate_signature(&mut &b"abc"[..], &options, &mut out_buf).unwrap();
+
+        let mut rs = RabinKarp::new();
+        rs.update(b"abc");
+        assert_eq!(&out_buf[..4], &[b'r', b's', 0x01, 0x47]);
+        assert_eq!(&out_buf[12..16], &rs.digest().to_be_bytes());
+        // BLAKE2b-256("abc").
+        assert_eq!(
+            &out_buf[16..],
+            &[
+                0xbd, 0xdd, 0x81, 0x3c, 0x63, 0x42, 0x39, 0x72, 0x31, 0x71, 0xef, 0x3f, 0xee, 0x98,
+                0x57, 0x9b, 0x94, 0x96, 0x4e, 0x3b, 0xb1, 0xcb, 0x3e, 0x42, 0x72, 0x62, 0xc8, 0xc0,
+                0x68, 0xd5, 0x23, 0x19,
+            ]
+        );
+    }
+
+    #[test]
+    pub fn strong_len_too_long() {
+        let options = SignatureOptions {
+            magic: SignatureFormat::Md4Sig,
+            ..SignatureOptions::default()
+        };
+        let err = generate_signature(&mut &b"abc"[..], &options, &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::InvalidStrongLen(32)));
+    }
+
+    #[test]
+    pub fn validate_options() {
+        let options = SignatureOptions::default();
+        assert!(options.validate().is_ok());
+        assert!(options.with_strong_len(1).validate().is_ok());
+        assert!(matches!(
+            options.with_block_len(0).validate(),
+            Err(Error::InvalidBlockLen(0))
+        ));
+        assert!(options.with_block_len(1 << 20).validate().is_ok());
+        assert!(options.with_block_len(1 << 28).validate().is_ok());
+        assert!(matches!(
+            options.with_block_len((1 << 28) + 1).validate(),
+            Err(Error::InvalidBlockLen(0x10000001))
+        ));
+        assert!(matches!(
+            options.with_block_len(u32::MAX).validate(),
+            Err(Error::InvalidBlockLen(u32::MAX))
+        ));
+        assert!(matches!(
+            options.with_strong_len(0).validate(),
+            Err(Error::InvalidStrongLen(0))
+        ));
+        assert!(matches!(
+            options.with_strong_len(33).validate(),
+            Err(Error::InvalidStrongLen(33))
+        ));
+        assert!(matches!(
+            options.with_magic(SignatureFormat::RkMd4Sig).validate(),
+            Err(Error::InvalidStrongLen(32))
+        ));
+        assert!(options
+            .with_magic(SignatureFormat::RkMd4Sig)
+            .with_strong_len(16)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    pub fn recommended_options() {
+        let small = SignatureOptions::recommended(0, SignatureFormat::RkBlake2Sig);
+        assert_eq!(small.magic, SignatureFormat::RkBlake2Sig);
+        assert_eq!(small.block_len, 256);
+        assert_eq!(small.strong_len, 5);
+
+        let config = SignatureOptions::recommended(4000, SignatureFormat::Blake2Sig);
+        assert_eq!(config.block_len, 256);
+        assert_eq!(config.strong_len, 6);
+
+        let image = SignatureOptions::recommended(1 << 30, SignatureFormat::RkBlake2Sig);
+        assert_eq!(image.block_len, 32768);
+        assert_eq!(image.strong_len, 8);
+
+        let odd = SignatureOptions::recommended(10_000_000, SignatureFormat::RkMd4Sig);
+        assert_eq!(odd.block_len, 3072);
+        assert_eq!(odd.strong_len, 7);
+
+        let huge = SignatureOptions::recommended(1 << 40, SignatureFormat::RkBlake2Sig);
+        assert_eq!(huge.block_len, 1 << 20);
+        assert_eq!(huge.strong_len, 10);
+        assert!(huge.validate().is_ok());
+
+        let largest = SignatureOptions::recommended(u64::MAX, SignatureFormat::RkBlake2Sig);
+        assert_eq!(largest.block_len, crate::MAX_BLOCK_LEN);
+        assert_eq!(largest.strong_len, 15);
+        assert!(largest.validate().is_ok());
+
+        // Smaller blocks than recommended need longer strong sums.
+        assert_eq!(min_strong_len(1 << 30, 32768), 8);
+        assert_eq!(min_strong_len(1 << 30, 1024), 9);
+    }
+
+    #[test]
+    pub fn zero_block_len() {
+        let options = SignatureOptions::default().with_block_len(0);
+        let err = generate_signature(&mut &b""[..], &options, &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::InvalidBlockLen(0)));
+    }
+
+    #[test]
+    pub fn md4_signature() {
+        let options = SignatureOptions {
+            magic: SignatureFormat::Md4Sig,
+            ..SignatureOptions::default()
+        }
+        .with_strong_len(16);
+        let mut out_buf = Vec::new();
+        generate_signature(&mut &b"abc"[..], &options, &mut out_buf).unwrap();
+
+        let mut expected = vec![b'r', b's', 0x01, 0x36, 0, 0, 8, 0, 0, 0, 0, 16];
+        expected.extend_from_slice(&[0x03, 0x04, 0x01, 0x83]);
+        // MD4("abc") from RFC 1320.
+        expected.extend_from_slice(&[
+            0xa4, 0x48, 0x01, 0x7a, 0xaf, 0x21, 0xd8, 0x52, 0x5f, 0xc1, 0x0a, 0xe8, 0x7a, 0xa6,
+            0x72, 0x9d,
+        ]);
+        assert_eq!(out_buf, expected);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    pub fn parallel_signature() {
+        let basis: Vec<u8> = (0..(9u32 << 20) + 1234)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect();
+        let options = [
+            SignatureOptions::default(),
+            SignatureOptions::recommended(basis.len() as u64, SignatureFormat::RkMd4Sig),
+            SignatureOptions::default()
+                .with_magic(SignatureFormat::RkKeyedBlake2Sig)
+                .with_block_len(3 << 20)
+                .with_key([1; KEY_LEN]),
+        ];
+        for options in &options {
+            for len in [0, 1, usize(options.block_len), basis.len()] {
+                let mut sequential = Vec::new();
+                generate_signature(&mut &basis[..len], options, &mut sequential).unwrap();
+                let mut parallel = Vec::new();
+                generate_signature_parallel(&mut &basis[..len], options, &mut parallel).unwrap();
+                assert_eq!(parallel, sequential);
+            }
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    pub fn parallel_signature_monitored() {
+        use crate::progress::{CancelToken, Monitor};
+
+        let basis = pseudo_random((9 << 20) + 1234, 16);
+        let options = SignatureOptions::default();
+        let mut seen = Vec::new();
+        let mut observer = |stats: &Stats| seen.push((stats.in_bytes, stats.blocks));
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        generate_signature_parallel_monitored(
+            &mut &basis[..],
+            &options,
+            &mut Vec::new(),
+            &mut monitor,
+        )
+        .unwrap();
+        assert_eq!(seen, [(8 << 20, 4096), ((9 << 20) + 1234, 4609)]);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut monitor = Monitor::new().with_cancel(&cancel);
+        let err = generate_signature_parallel_monitored(
+            &mut &basis[..],
+            &options,
+            &mut Vec::new(),
+            &mut monitor,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+    }
+
+    #[test]
+    pub fn keyed_signature() {
+        let key: [u8; KEY_LEN] = std::array::from_fn(|i| i as u8);
+        let options = SignatureOptions::default()
+            .with_magic(SignatureFormat::KeyedBlake2Sig)
+            .with_strong_len(8)
+            .with_key(key);
+        let mut out_buf = Vec::new();
+        generate_signature(&mut &b"abc"[..], &options, &mut out_buf).unwrap();
+
+        let mut expected = vec![b'r', b's', 0x01, 0x38, 0, 0, 8, 0, 0, 0, 0, 8];
+        expected.extend_from_slice(&key);
+        expected.extend_from_slice(&[0x03, 0x04, 0x01, 0x83]);
+        // hashlib.blake2b(b"abc", key=bytes(range(16)), digest_size=32), truncated.
+        expected.extend_from_slice(&[0x3f, 0xd8, 0xfd, 0x31, 0x50, 0x1c, 0xdb, 0xe9]);
+        assert_eq!(out_buf, expected);
+
+        assert!(matches!(
+            options.with_magic(SignatureFormat::Blake2Sig).validate(),
+            Err(Error::InvalidKey)
+        ));
+        assert!(matches!(
+            SignatureOptions::default()
+                .with_magic(SignatureFormat::RkKeyedBlake2Sig)
+                .validate(),
+            Err(Error::InvalidKey)
+        ));
+
+        // The generic hash would ignore the key.
+        let err =
+            generate_signature_with_hash::<Blake2>(&mut &b"abc"[..], &options, &mut Vec::new())
+                .unwrap_err();
+        assert!(matches!(err, Error::InvalidKey));
+    }
+
+    #[test]
+    pub fn signature_with_hash() {
+        let basis = pseudo_random(20_000, 8);
+
+        // The hash a format names gives the same signature either way.
+        let options = SignatureOptions::default();
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        let mut generic = Vec::new();
+        generate_signature_with_hash::<Blake2>(&mut &basis[..], &options, &mut generic).unwrap();
+        assert_eq!(generic, sig);
+
+        // A header naming one hash must not hold sums of another.
+        let err = generate_signature_with_hash::<Md4>(&mut &basis[..], &options, &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0x72730137)));
+    }
 }
@@ -0,0 +1,303 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use cast::usize;
+use memmap2::Mmap;
+
+use crate::delta::{scanner, Command, DELTA_MAGIC, INPUT_CHUNK_LEN};
+use crate::error::{Error, Result};
+use crate::mksum::{write_header, write_u32be, SignatureOptions};
+use crate::patch::{copy_exact, read_magic};
+use crate::progress::Monitor;
+use crate::stats::{Counted, Stats};
+use crate::sumset::Signature;
+
+/// Maps the file at `path` into memory.
+fn map(path: &Path) -> Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: the functions below are documented as requiring that nothing
+    // modifies the file while it is mapped.
+    Ok(unsafe { Mmap::map(&file)? })
+}
+
+/// `mksum::generate_signature` of the file at `basis`, which is mapped into memory
+/// instead of read. The file must not be modified until this returns.
+pub fn signature_file(
+    basis: impl AsRef<Path>,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+) -> Result<Stats> {
+    signature_file_monitored(basis, options, sig, &mut Monitor::new())
+}
+
+/// Like `signature_file`, reporting to and checking `monitor` after each block.
+pub fn signature_file_monitored(
+    basis: impl AsRef<Path>,
+    options: &SignatureOptions,
+    sig: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    options.validate()?;
+    let basis = map(basis.as_ref())?;
+    let block_sums = options.block_sums();
+
+    let sig = &mut Counted::new(BufWriter::new(sig));
+    write_header(options, sig)?;
+    for block in basis.chunks(usize(options.block_len)) {
+        block_sums(block, sig)?;
+        stats.in_bytes += block.len() as u64;
+        stats.blocks += 1;
+        stats.out_bytes = sig.bytes;
+        stats.elapsed = start.elapsed();
+        monitor.check(&stats)?;
+    }
+    sig.flush()?;
+    stats.out_bytes = sig.bytes;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+/// `delta::generate_delta` of the file at `new_file`, which is mapped into memory
+/// instead of read. The file must not be modified until this returns.
+pub fn delta_file(
+    sig: &mut dyn Read,
+    new_file: impl AsRef<Path>,
+    delta: &mut dyn Write,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let sig = Signature::load(sig)?;
+    let new_file = map(new_file.as_ref())?;
+
+    let delta = &mut Counted::new(BufWriter::new(delta));
+    write_u32be(delta, DELTA_MAGIC)?;
+    let mut scanner = scanner(&sig);
+    scanner.scan(&new_file, true, delta)?;
+    Command::End.write_to(delta)?;
+    delta.flush()?;
+    Ok(Stats {
+        out_bytes: delta.bytes,
+        elapsed: start.elapsed(),
+        ..*scanner.stats()
+    })
+}
+
+/// Like `delta_file`, reporting to and checking `monitor` after each chunk of
+/// the new file.
+pub fn delta_file_monitored(
+    sig: &mut dyn Read,
+    new_file: impl AsRef<Path>,
+    delta: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let sig = Signature::load(sig)?;
+    let new_file = map(new_file.as_ref())?;
+
+    let delta = &mut Counted::new(BufWriter::new(delta));
+    write_u32be(delta, DELTA_MAGIC)?;
+    let mut scanner = scanner(&sig);
+    let mut end = 0;
+    loop {
+        end = (end + INPUT_CHUNK_LEN).min(new_file.len());
+        let eof = end == new_file.len();
+        scanner.scan_in_place(&new_file[..end], eof, delta)?;
+        monitor.check(&Stats {
+            out_bytes: delta.bytes,
+            elapsed: start.elapsed(),
+            ..*scanner.stats()
+        })?;
+        if eof {
+            break;
+        }
+    }
+    Command::End.write_to(delta)?;
+    delta.flush()?;
+    Ok(Stats {
+        out_bytes: delta.bytes,
+        elapsed: start.elapsed(),
+        ..*scanner.stats()
+    })
+}
+
+/// `patch::apply_patch` with the file at `basis` mapped into memory, so copies
+/// are written straight from the mapping. The file must not be modified until
+/// this returns.
+pub fn patch_file(
+    basis: impl AsRef<Path>,
+    delta: &mut dyn Read,
+    out: &mut dyn Write,
+) -> Result<Stats> {
+    patch_file_monitored(basis, delta, out, &mut Monitor::new())
+}
+
+/// Like `patch_file`, reporting to and checking `monitor` after each command.
+pub fn patch_file_monitored(
+    basis: impl AsRef<Path>,
+    delta: &mut dyn Read,
+    out: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    let basis = map(basis.as_ref())?;
+    let delta = &mut Counted::new(BufReader::new(delta));
+    let out = &mut Counted::new(BufWriter::new(out));
+    read_magic(delta)?;
+
+    loop {
+        let command = Command::read_from(delta)?;
+        stats.count_command(&command);
+        match command {
+            Command::End => break,
+            Command::Literal { len } => copy_exact(
+                delta,
+                len,
+                out,
+                Error::CorruptDelta("truncated literal data"),
+            )?,
+            Command::Copy { offset, len } => {
+                let data = offset
+                    .checked_add(len)
+                    .filter(|&end| end <= basis.len() as u64)
+                    .map(|end| &basis[offset as usize..end as usize])
+                    .ok_or(Error::CorruptDelta("copy past the end of the basis"))?;
+                out.write_all(data)?;
+            }
+        }
+        stats.in_bytes = delta.bytes;
+        stats.out_bytes = out.bytes;
+        stats.elapsed = start.elapsed();
+        monitor.check(&stats)?;
+    }
+    out.flush()?;
+    stats.in_bytes = delta.bytes;
+    stats.out_bytes = out.bytes;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::delta::generate_delta;
+    use crate::mksum::{generate_signature, SignatureFormat};
+    use crate::patch::apply_patch;
+    use crate::progress::CancelToken;
+    use crate::testutil::pseudo_random;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    fn scratch_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("rdiff-mmap-{}-{}", name, process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn check_matches_streams(name: &str, basis: &[u8], new: &[u8], options: &SignatureOptions) {
+        let basis_path = scratch_file(&format!("{}-basis", name), basis);
+        let new_path = scratch_file(&format!("{}-new", name), new);
+
+        let mut sig = Vec::new();
+        generate_signature(&mut &basis[..], options, &mut sig).unwrap();
+        let mut mapped_sig = Vec::new();
+        signature_file(&basis_path, options, &mut mapped_sig).unwrap();
+        assert_eq!(mapped_sig, sig);
+
+        let mut delta = Vec::new();
+        generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+        let mut mapped_delta = Vec::new();
+        delta_file(&mut &sig[..], &new_path, &mut mapped_delta).unwrap();
+        assert_eq!(mapped_delta, delta);
+
+        let mut out = Vec::new();
+        apply_patch(&mut Cursor::new(basis), &mut &delta[..], &mut out).unwrap();
+        let mut mapped_out = Vec::new();
+        patch_file(&basis_path, &mut &delta[..], &mut mapped_out).unwrap();
+        assert_eq!(mapped_out, out);
+        assert_eq!(mapped_out, new);
+
+        fs::remove_file(basis_path).unwrap();
+        fs::remove_file(new_path).unwrap();
+    }
+
+    #[test]
+    pub fn matches_streams() {
+        let basis = pseudo_random(300_000, 1);
+        let mut new = basis.clone();
+        new.splice(100_000..100_000, pseudo_random(5000, 2));
+        new.drain(200_000..210_000);
+        let options =
+            SignatureOptions::recommended(basis.len() as u64, SignatureFormat::RkBlake2Sig);
+        check_matches_streams("edited", &basis, &new, &options);
+        check_matches_streams("empty", &[], &new[..1000], &SignatureOptions::default());
+        check_matches_streams("to-empty", &basis, &[], &SignatureOptions::default());
+    }
+
+    #[test]
+    pub fn monitored() {
+        let basis = pseudo_random(200_000, 17);
+        let mut new = basis.clone();
+        new.splice(100_000..100_000, pseudo_random(100, 18));
+        let basis_path = scratch_file("monitored-basis", &basis);
+        let new_path = scratch_file("monitored-new", &new);
+        let options = SignatureOptions::default().with_block_len(1000);
+
+        let mut blocks = Vec::new();
+        let mut observer = |stats: &Stats| blocks.push(stats.blocks);
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut sig = Vec::new();
+        signature_file_monitored(&basis_path, &options, &mut sig, &mut monitor).unwrap();
+        assert_eq!(blocks, (1..=200).collect::<Vec<_>>());
+
+        let mut chunks = 0;
+        let mut observer = |_: &Stats| chunks += 1;
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut delta = Vec::new();
+        delta_file_monitored(&mut &sig[..], &new_path, &mut delta, &mut monitor).unwrap();
+        assert_eq!(chunks, new.len().div_ceil(INPUT_CHUNK_LEN));
+        let mut unmonitored = Vec::new();
+        delta_file(&mut &sig[..], &new_path, &mut unmonitored).unwrap();
+        assert_eq!(delta, unmonitored);
+
+        let mut last = Stats::default();
+        let mut observer = |stats: &Stats| last = *stats;
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut out = Vec::new();
+        patch_file_monitored(&basis_path, &mut &delta[..], &mut out, &mut monitor).unwrap();
+        assert_eq!(out, new);
+        assert_eq!(last.out_bytes, new.len() as u64);
+
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        let mut monitor = Monitor::new().with_cancel(&cancel);
+        let err = signature_file_monitored(&basis_path, &options, &mut Vec::new(), &mut monitor)
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        let err = delta_file_monitored(&mut &sig[..], &new_path, &mut Vec::new(), &mut monitor)
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        let err = patch_file_monitored(&basis_path, &mut &delta[..], &mut Vec::new(), &mut monitor)
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+
+        fs::remove_file(basis_path).unwrap();
+        fs::remove_file(new_path).unwrap();
+    }
+
+    #[test]
+    pub fn copy_past_end_of_basis() {
+        let basis_path = scratch_file("short-basis", b"0123456789");
+        let delta = [b'r', b's', 0x02, 0x36, 0x45, 0x08, 0x08, 0x00];
+        let err = patch_file(&basis_path, &mut &delta[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CorruptDelta("copy past the end of the basis")
+        ));
+        fs::remove_file(basis_path).unwrap();
+    }
+}
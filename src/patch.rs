@@ -0,0 +1,204 @@
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::time::Instant;
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use crate::delta::{Command, DELTA_MAGIC};
+use crate::error::{Error, Result};
+use crate::progress::Monitor;
+use crate::stats::{Counted, Stats};
+
+pub(crate) fn copy_exact(
+    from: &mut dyn Read,
+    len: u64,
+    out: &mut dyn Write,
+    short: Error,
+) -> Result<()> {
+    let copied = io::copy(&mut from.take(len), out)?;
+    if copied < len {
+        return Err(short);
+    }
+    Ok(())
+}
+
+pub(crate) fn read_magic(delta: &mut dyn Read) -> Result<()> {
+    let magic = delta
+        .read_u32::<BigEndian>()
+        .map_err(|e| Error::truncated(e, Error::CorruptDelta("truncated header")))?;
+    if magic != DELTA_MAGIC {
+        return Err(Error::BadMagic(magic));
+    }
+    Ok(())
+}
+
+pub fn apply_patch<B: Read + Seek>(
+    basis: &mut B,
+    delta: &mut dyn Read,
+    out: &mut dyn Write,
+) -> Result<Stats> {
+    apply_patch_monitored(basis, delta, out, &mut Monitor::new())
+}
+
+/// Like `apply_patch`, reporting to and checking `monitor` after each command.
+pub fn apply_patch_monitored<B: Read + Seek>(
+    basis: &mut B,
+    delta: &mut dyn Read,
+    out: &mut dyn Write,
+    monitor: &mut Monitor,
+) -> Result<Stats> {
+    let start = Instant::now();
+    let mut stats = Stats::default();
+    let delta = &mut Counted::new(BufReader::new(delta));
+    let out = &mut Counted::new(BufWriter::new(out));
+
+    read_magic(delta)?;
+
+    loop {
+        let command = Command::read_from(delta)?;
+        stats.count_command(&command);
+        match command {
+            Command::End => break,
+            Command::Literal { len } => copy_exact(
+                delta,
+                len,
+                out,
+                Error::CorruptDelta("truncated literal data"),
+            )?,
+            Command::Copy { offset, len } => {
+                basis.seek(SeekFrom::Start(offset))?;
+                copy_exact(
+                    basis,
+                    len,
+                    out,
+                    Error::CorruptDelta("copy past the end of the basis"),
+                )?;
+            }
+        }
+        stats.in_bytes = delta.bytes;
+        stats.out_bytes = out.bytes;
+        stats.elapsed = start.elapsed();
+        monitor.check(&stats)?;
+    }
+    out.flush()?;
+    stats.in_bytes = delta.bytes;
+    stats.out_bytes = out.bytes;
+    stats.elapsed = start.elapsed();
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mksum::{SignatureFormat, SignatureOptions};
+    use crate::testutil::{pseudo_random, round_trip_with};
+    use std::io::Cursor;
+
+    fn round_trip(basis: &[u8], new: &[u8], block_len: u32) -> Vec<u8> {
+        let options = SignatureOptions {
+            block_len,
+            ..SignatureOptions::default()
+        };
+        round_trip_with(basis, new, &options)
+    }
+
+    #[test]
+    pub fn hand_written_delta() {
+        let delta = [
+            b'r', b's', 0x02, 0x36, 0x02, b'>', b' ', 0x45, 0x06, 0x05, 0x00,
+        ];
+        let mut out_buf = Vec::new();
+        apply_patch(
+            &mut Cursor::new(b"Hello world\n"),
+            &mut &delta[..],
+            &mut out_buf,
+        )
+        .unwrap();
+        assert_eq!(out_buf, b"> world");
+    }
+
+    #[test]
+    pub fn round_trips() {
+        let basis = pseudo_random(100_000, 1);
+        let mut new = basis.clone();
+        new[5000..5100].copy_from_slice(&pseudo_random(100, 2));
+        new.splice(40_000..40_000, pseudo_random(3000, 3));
+        new.drain(70_000..75_000);
+        new.extend_from_slice(&basis[..1234]);
+
+        assert_eq!(round_trip(&basis, &new, 2048), new);
+        assert_eq!(round_trip(&basis, &new, 1), new);
+        assert_eq!(round_trip(&basis, &new, 777), new);
+        assert_eq!(round_trip(&basis, &new, 70_000), new);
+        assert_eq!(round_trip(&[], &new, 2048), new);
+        assert_eq!(round_trip(&basis, &[], 2048), b"");
+        assert_eq!(round_trip(&basis, &basis[..1000], 2048), &basis[..1000]);
+    }
+
+    #[test]
+    pub fn md4_round_trip() {
+        let basis = pseudo_random(20_000, 4);
+        let mut new = basis.clone();
+        new.splice(10_000..10_000, pseudo_random(100, 5));
+        let options = SignatureOptions {
+            magic: SignatureFormat::Md4Sig,
+            block_len: 512,
+            strong_len: 8,
+            key: None,
+        };
+        assert_eq!(round_trip_with(&basis, &new, &options), new);
+    }
+
+    #[test]
+    pub fn rabinkarp_round_trip() {
+        let basis = pseudo_random(20_000, 6);
+        let mut new = basis.clone();
+        new.splice(10_000..10_000, pseudo_random(100, 7));
+        new.drain(15_000..15_300);
+        for magic in [SignatureFormat::RkBlake2Sig, SignatureFormat::RkMd4Sig] {
+            let options = SignatureOptions {
+                magic,
+                block_len: 512,
+                strong_len: 8,
+                key: None,
+            };
+            assert_eq!(round_trip_with(&basis, &new, &options), new);
+        }
+    }
+
+    #[test]
+    pub fn bad_magic() {
+        let err = apply_patch(
+            &mut Cursor::new(b""),
+            &mut &b"rs\x01\x37"[..],
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0x72730137)));
+    }
+
+    #[test]
+    pub fn copy_past_end_of_basis() {
+        let delta = [b'r', b's', 0x02, 0x36, 0x45, 0x08, 0x08, 0x00];
+        let err = apply_patch(
+            &mut Cursor::new(b"0123456789"),
+            &mut &delta[..],
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CorruptDelta("copy past the end of the basis")
+        ));
+    }
+
+    #[test]
+    pub fn missing_end_command() {
+        let delta = [b'r', b's', 0x02, 0x36, 0x01, b'x'];
+        let err = apply_patch(&mut Cursor::new(b""), &mut &delta[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("missing end command")));
+
+        let delta = [b'r', b's', 0x02, 0x36, 0x02, b'x'];
+        let err = apply_patch(&mut Cursor::new(b""), &mut &delta[..], &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, Error::CorruptDelta("truncated literal data")));
+    }
+}
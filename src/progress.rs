@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use crate::stats::Stats;
+
+/// Stops an operation from another thread; clones share the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// An optional progress observer and cancellation token, consulted between
+/// blocks, chunks of the new file or delta commands.
+#[derive(Default)]
+pub struct Monitor<'a> {
+    observer: Option<&'a mut (dyn FnMut(&Stats) + Send)>,
+
+    cancel: Option<&'a CancelToken>,
+}
+
+impl<'a> Monitor<'a> {
+    pub fn new() -> Monitor<'a> {
+        Monitor::default()
+    }
+
+    /// Calls `observer` with the counts so far each time the operation checks in.
+    pub fn with_observer(self, observer: &'a mut (dyn FnMut(&Stats) + Send)) -> Monitor<'a> {
+        Monitor {
+            observer: Some(observer),
+            ..self
+        }
+    }
+
+    /// Stops the operation with `Error::Cancelled` once `cancel` is cancelled.
+    pub fn with_cancel(self, cancel: &'a CancelToken) -> Monitor<'a> {
+        Monitor {
+            cancel: Some(cancel),
+            ..self
+        }
+    }
+
+    pub(crate) fn check(&mut self, stats: &Stats) -> Result<()> {
+        if let Some(observer) = &mut self.observer {
+            observer(stats);
+        }
+        match self.cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::delta::{generate_delta_from_signature_monitored, generate_delta_monitored};
+    use crate::mksum::{generate_signature_monitored, SignatureOptions};
+    use crate::patch::apply_patch_monitored;
+    use crate::sumset::Signature;
+    use crate::testutil::pseudo_random;
+    use crate::Error;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn progress_and_cancel() {
+        let basis = pseudo_random(200_000, 14);
+        let mut new = basis.clone();
+        new.splice(100_000..100_000, pseudo_random(100, 15));
+        let options = SignatureOptions::default().with_block_len(1000);
+
+        let mut seen = Vec::new();
+        let mut observer = |stats: &Stats| seen.push((stats.in_bytes, stats.blocks));
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut sig = Vec::new();
+        generate_signature_monitored(&mut &basis[..], &options, &mut sig, &mut monitor).unwrap();
+        assert_eq!(seen.len(), 200);
+        assert_eq!(seen[0], (1000, 1));
+        assert_eq!(seen[199], (200_000, 200));
+
+        // Cancelling from the observer stops at the next check.
+        let cancel = CancelToken::new();
+        let mut blocks = 0;
+        let mut observer = |stats: &Stats| {
+            blocks = stats.blocks;
+            if stats.blocks == 3 {
+                cancel.cancel();
+            }
+        };
+        let mut monitor = Monitor::new()
+            .with_observer(&mut observer)
+            .with_cancel(&cancel);
+        let err =
+            generate_signature_monitored(&mut &basis[..], &options, &mut Vec::new(), &mut monitor)
+                .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert_eq!(blocks, 3);
+
+        let loaded = Signature::load(&mut &sig[..]).unwrap();
+        let mut chunks = 0;
+        let mut observer = |_: &Stats| chunks += 1;
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut delta = Vec::new();
+        generate_delta_from_signature_monitored(&loaded, &mut &new[..], &mut delta, &mut monitor)
+            .unwrap();
+        assert!(chunks > 1);
+
+        let mut monitor = Monitor::new().with_cancel(&cancel);
+        let err =
+            generate_delta_monitored(&mut &sig[..], &mut &new[..], &mut Vec::new(), &mut monitor)
+                .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+
+        let mut last = Stats::default();
+        let mut observer = |stats: &Stats| last = *stats;
+        let mut monitor = Monitor::new().with_observer(&mut observer);
+        let mut out = Vec::new();
+        apply_patch_monitored(
+            &mut Cursor::new(&basis),
+            &mut &delta[..],
+            &mut out,
+            &mut monitor,
+        )
+        .unwrap();
+        assert_eq!(out, new);
+        assert_eq!(last.out_bytes, new.len() as u64);
+
+        let mut monitor = Monitor::new().with_cancel(&cancel);
+        let mut out = Vec::new();
+        let err = apply_patch_monitored(
+            &mut Cursor::new(&basis),
+            &mut &delta[..],
+            &mut out,
+            &mut monitor,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert!(out.len() < new.len());
+    }
+}
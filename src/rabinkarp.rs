@@ -0,0 +1,147 @@
+use std::num::Wrapping;
+
+use crate::rollsum::Rollsum;
+
+const SEED: Wrapping<u32> = Wrapping(1);
+
+const MULT: Wrapping<u32> = Wrapping(0x08104225);
+
+/// Multiplicative inverse of `MULT` modulo 2^32.
+const INVM: Wrapping<u32> = Wrapping(0x98f009ad);
+
+/// Removes both an outgoing byte and its share of the seed: `MULT - 1`.
+const ADJ: Wrapping<u32> = Wrapping(0x08104224);
+
+#[derive(Debug, Copy, Clone)]
+pub struct RabinKarp {
+    count: usize,
+
+    hash: Wrapping<u32>,
+
+    /// `MULT` to the power of `count`.
+    mult: Wrapping<u32>,
+}
+
+impl RabinKarp {
+    pub fn new() -> RabinKarp {
+        RabinKarp::default()
+    }
+}
+
+impl Default for RabinKarp {
+    fn default() -> RabinKarp {
+        RabinKarp {
+            count: 0,
+            hash: SEED,
+            mult: Wrapping(1),
+        }
+    }
+}
+
+impl Rollsum for RabinKarp {
+    fn digest(&self) -> u32 {
+        self.hash.0
+    }
+
+    fn roll_in(&mut self, c_in: u8) {
+        self.hash = self.hash * MULT + Wrapping(c_in as u32);
+        self.count += 1;
+        self.mult *= MULT;
+    }
+
+    fn roll_out(&mut self, c_out: u8) {
+        self.count -= 1;
+        self.mult *= INVM;
+        self.hash -= self.mult * (Wrapping(c_out as u32) + ADJ);
+    }
+
+    fn rotate(&mut self, c_out: u8, c_in: u8) {
+        self.hash =
+            self.hash * MULT + Wrapping(c_in as u32) - self.mult * (Wrapping(c_out as u32) + ADJ);
+    }
+
+    fn update(&mut self, buf: &[u8]) {
+        let mut hash = self.hash;
+        let mut mult = self.mult;
+        for c in buf {
+            hash = hash * MULT + Wrapping(*c as u32);
+            mult *= MULT;
+        }
+        self.hash = hash;
+        self.mult = mult;
+        self.count += buf.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn inverse() {
+        assert_eq!((MULT * INVM).0, 1);
+        assert_eq!(ADJ, MULT - Wrapping(1));
+    }
+
+    #[test]
+    pub fn default_value() {
+        let rs = RabinKarp::new();
+        assert_eq!(rs.count, 0);
+        assert_eq!(rs.mult.0, 1);
+        assert_eq!(rs.digest(), 1);
+    }
+
+    #[test]
+    pub fn rabinkarp() {
+        let mut rs = RabinKarp::new();
+        rs.roll_in(0u8);
+        assert_eq!(rs.count, 1);
+        assert_eq!(rs.digest(), 0x08104225);
+
+        rs.roll_in(1u8);
+        rs.roll_in(2u8);
+        rs.roll_in(3u8);
+        let mut fresh = RabinKarp::new();
+        fresh.update(&[0, 1, 2, 3]);
+        assert_eq!(rs.digest(), fresh.digest());
+
+        rs.rotate(0, 4);
+        rs.rotate(1, 5);
+        let mut fresh = RabinKarp::new();
+        fresh.update(&[2, 3, 4, 5]);
+        assert_eq!(rs.count, 4);
+        assert_eq!(rs.digest(), fresh.digest());
+
+        rs.roll_out(2);
+        let mut fresh = RabinKarp::new();
+        fresh.update(&[3, 4, 5]);
+        assert_eq!(rs.count, 3);
+        assert_eq!(rs.digest(), fresh.digest());
+
+        rs.roll_out(3);
+        rs.roll_out(4);
+        rs.roll_out(5);
+        assert_eq!(rs.count, 0);
+        assert_eq!(rs.digest(), 1);
+        assert_eq!(rs.mult.0, 1);
+    }
+
+    #[test]
+    pub fn update() {
+        let mut rs = RabinKarp::new();
+        let mut buf = [0u8; 256];
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        rs.update(&buf[..100]);
+        rs.update(&buf[100..]);
+
+        let mut rolled = RabinKarp::new();
+        for c in buf {
+            rolled.roll_in(c);
+        }
+        assert_eq!(rs.count, 256);
+        assert_eq!(rs.digest(), rolled.digest());
+        assert_eq!(rs.mult, rolled.mult);
+    }
+}
@@ -4,7 +4,7 @@ const CHAR_OFFSET: Wrapping<u16> = Wrapping(31);
 
 #[derive(Debug, Default, Copy, Clone)]
 pub struct Window {
-    count: Wrapping<u16>,
+    count: usize,
 
     s1: Wrapping<u16>,
 
@@ -15,6 +15,32 @@ impl Window {
     pub fn new() -> Window {
         Window::default()
     }
+
+    /// `update` without vector instructions, which it is benchmarked against.
+    #[cfg(any(feature = "bench", test))]
+    #[doc(hidden)]
+    pub fn update_scalar(&mut self, buf: &[u8]) {
+        let sums = sum_bytes_scalar(self.s1, self.s2, buf);
+        self.add_sums(sums, buf.len());
+    }
+
+    /// Takes the sums of `len` bytes from `sum_bytes` and adds their `CHAR_OFFSET` terms.
+    fn add_sums(&mut self, (mut s1, mut s2): (Wrapping<u16>, Wrapping<u16>), len: usize) {
+        // len * (len + 1) / 2 modulo 2^16, halving whichever factor is even
+        // first so that the product cannot overflow.
+        let trilen = if len.is_multiple_of(2) {
+            Wrapping((len / 2) as u16) * Wrapping(len.wrapping_add(1) as u16)
+        } else {
+            Wrapping(len as u16) * Wrapping((len / 2 + 1) as u16)
+        };
+
+        s1 += Wrapping(len as u16) * CHAR_OFFSET;
+        s2 += trilen * CHAR_OFFSET;
+
+        self.count += len;
+        self.s1 = s1;
+        self.s2 = s2;
+    }
 }
 
 pub trait Rollsum {
@@ -37,51 +63,186 @@ impl Rollsum for Window {
     fn roll_in(&mut self, c_in: u8) {
         self.s1 += CHAR_OFFSET + Wrapping(c_in as u16);
         self.s2 += self.s1;
-        self.count += Wrapping(1);
+        self.count += 1;
     }
 
     fn roll_out(&mut self, c_out: u8) {
         let c_out = Wrapping(c_out as u16);
         self.s1 -= c_out + CHAR_OFFSET;
-        self.s2 -= self.count * (c_out + CHAR_OFFSET);
-        self.count -= Wrapping(1);
+        self.s2 -= Wrapping(self.count as u16) * (c_out + CHAR_OFFSET);
+        self.count -= 1;
     }
 
     fn rotate(&mut self, c_out: u8, c_in: u8) {
         let c_in = Wrapping(c_in as u16);
         let c_out = Wrapping(c_out as u16);
         self.s1 += c_in - c_out;
-        self.s2 += self.s1 - (self.count * (c_out + CHAR_OFFSET));
+        self.s2 += self.s1 - (Wrapping(self.count as u16) * (c_out + CHAR_OFFSET));
     }
 
     fn update(&mut self, buf: &[u8]) {
-        let mut s1 = self.s1;
-        let mut s2 = self.s2;
-        for c in buf {
-            s1 += Wrapping(*c as u16);
-            s2 += s1;
+        let sums = sum_bytes(self.s1, self.s2, buf);
+        self.add_sums(sums, buf.len());
+    }
+}
+
+/// Adds `buf` to the sums without the `CHAR_OFFSET` terms, with the widest
+/// vector instructions the CPU has.
+fn sum_bytes(s1: Wrapping<u16>, s2: Wrapping<u16>, buf: &[u8]) -> (Wrapping<u16>, Wrapping<u16>) {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            // SAFETY: the CPU supports AVX2.
+            return unsafe { x86::sum_bytes_avx2(s1, s2, buf) };
+        }
+        if is_x86_feature_detected!("sse2") {
+            // SAFETY: the CPU supports SSE2.
+            return unsafe { x86::sum_bytes_sse2(s1, s2, buf) };
         }
-        let len = buf.len() as u32;
-        let ll = Wrapping(buf.len() as u16);
-        let trilen = Wrapping(((len * (len + 1)) / 2) as u16);
+    }
+    sum_bytes_scalar(s1, s2, buf)
+}
 
-        s1 += ll * CHAR_OFFSET;
-        s2 += trilen * CHAR_OFFSET;
+fn sum_bytes_scalar(
+    mut s1: Wrapping<u16>,
+    mut s2: Wrapping<u16>,
+    buf: &[u8],
+) -> (Wrapping<u16>, Wrapping<u16>) {
+    for c in buf {
+        s1 += Wrapping(*c as u16);
+        s2 += s1;
+    }
+    (s1, s2)
+}
 
-        self.count += ll;
-        self.s1 = s1;
-        self.s2 = s2;
+/// Over a chunk of `n` bytes the sums grow by
+///
+///   s1 += c[0] + ... + c[n-1]
+///   s2 += n * s1 + n * c[0] + (n-1) * c[1] + ... + 1 * c[n-1]
+///
+/// so each step takes a vector of bytes at once: their plain sum for `s1`,
+/// and a sum weighted by distance from the end of the vector for `s2`, plus
+/// the vector's length times the `s1` of the previous vectors. Lanes are 32
+/// bits and wrap, which keeps the low 16 bits exact.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+    use std::num::Wrapping;
+
+    use super::sum_bytes_scalar;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn sum_bytes_avx2(
+        s1: Wrapping<u16>,
+        s2: Wrapping<u16>,
+        buf: &[u8],
+    ) -> (Wrapping<u16>, Wrapping<u16>) {
+        let chunks = buf.chunks_exact(32);
+        let rest = chunks.remainder();
+        let n = buf.len() - rest.len();
+
+        let zero = _mm256_setzero_si256();
+        let ones = _mm256_set1_epi16(1);
+        let weights = _mm256_setr_epi8(
+            32, 31, 30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 15, 14, 13, 12, 11,
+            10, 9, 8, 7, 6, 5, 4, 3, 2, 1,
+        );
+        let mut v_s1 = zero;
+        let mut v_prev = zero;
+        let mut v_s2 = zero;
+        for chunk in chunks {
+            let v = _mm256_loadu_si256(chunk.as_ptr() as *const __m256i);
+            v_prev = _mm256_add_epi32(v_prev, v_s1);
+            v_s1 = _mm256_add_epi32(v_s1, _mm256_sad_epu8(v, zero));
+            let weighted = _mm256_maddubs_epi16(v, weights);
+            v_s2 = _mm256_add_epi32(v_s2, _mm256_madd_epi16(weighted, ones));
+        }
+        let v_s2 = _mm256_add_epi32(v_s2, _mm256_slli_epi32(v_prev, 5));
+        let (s1, s2) = combine(s1, s2, n, hsum_256(v_s1), hsum_256(v_s2));
+        sum_bytes_scalar(s1, s2, rest)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn sum_bytes_sse2(
+        s1: Wrapping<u16>,
+        s2: Wrapping<u16>,
+        buf: &[u8],
+    ) -> (Wrapping<u16>, Wrapping<u16>) {
+        let chunks = buf.chunks_exact(16);
+        let rest = chunks.remainder();
+        let n = buf.len() - rest.len();
+
+        let zero = _mm_setzero_si128();
+        let weights_lo = _mm_setr_epi16(16, 15, 14, 13, 12, 11, 10, 9);
+        let weights_hi = _mm_setr_epi16(8, 7, 6, 5, 4, 3, 2, 1);
+        let mut v_s1 = zero;
+        let mut v_prev = zero;
+        let mut v_s2 = zero;
+        for chunk in chunks {
+            let v = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+            v_prev = _mm_add_epi32(v_prev, v_s1);
+            v_s1 = _mm_add_epi32(v_s1, _mm_sad_epu8(v, zero));
+            let lo = _mm_madd_epi16(_mm_unpacklo_epi8(v, zero), weights_lo);
+            let hi = _mm_madd_epi16(_mm_unpackhi_epi8(v, zero), weights_hi);
+            v_s2 = _mm_add_epi32(v_s2, _mm_add_epi32(lo, hi));
+        }
+        let v_s2 = _mm_add_epi32(v_s2, _mm_slli_epi32(v_prev, 4));
+        let (s1, s2) = combine(s1, s2, n, hsum_128(v_s1), hsum_128(v_s2));
+        sum_bytes_scalar(s1, s2, rest)
+    }
+
+    /// Folds the sums of the first `n` bytes into the running sums.
+    fn combine(
+        s1: Wrapping<u16>,
+        s2: Wrapping<u16>,
+        n: usize,
+        sum: u32,
+        weighted: u32,
+    ) -> (Wrapping<u16>, Wrapping<u16>) {
+        let s2 = s2 + Wrapping(n as u16) * s1 + Wrapping(weighted as u16);
+        (s1 + Wrapping(sum as u16), s2)
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn hsum_256(v: __m256i) -> u32 {
+        let v = _mm_add_epi32(_mm256_castsi256_si128(v), _mm256_extracti128_si256::<1>(v));
+        hsum_128(v)
+    }
+
+    #[target_feature(enable = "sse2")]
+    unsafe fn hsum_128(v: __m128i) -> u32 {
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b01_00_11_10>(v));
+        let v = _mm_add_epi32(v, _mm_shuffle_epi32::<0b10_11_00_01>(v));
+        _mm_cvtsi128_si32(v) as u32
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::{Rollsum, Window};
+    use crate::testutil::pseudo_random;
+    use proptest::prelude::*;
+
+    /// The digest computed straight from its definition, with wide sums.
+    fn reference_digest(buf: &[u8]) -> u32 {
+        let n = buf.len() as u64;
+        let mut s1 = 0u64;
+        let mut s2 = 0u64;
+        for (i, c) in buf.iter().enumerate() {
+            let c = *c as u64 + 31;
+            s1 = s1.wrapping_add(c);
+            s2 = s2.wrapping_add((n - i as u64).wrapping_mul(c));
+        }
+        ((s2 & 0xffff) << 16 | (s1 & 0xffff)) as u32
+    }
 
     #[test]
     pub fn default_value() {
         let rs = Window::new();
-        assert_eq!(rs.count.0, 0);
+        assert_eq!(rs.count, 0);
         assert_eq!(rs.s1.0, 0);
         assert_eq!(rs.s2.0, 0);
         assert_eq!(rs.digest(), 0u32);
@@ -91,37 +252,38 @@ mod test {
     pub fn rollsum() {
         let mut rs = Window::new();
         rs.roll_in(0u8);
-        assert_eq!(rs.count.0, 1);
+        assert_eq!(rs.count, 1);
         assert_eq!(rs.digest(), 0x001f001f);
 
         rs.roll_in(1u8);
         rs.roll_in(2u8);
         rs.roll_in(3u8);
-        assert_eq!(rs.count.0, 4);
+        assert_eq!(rs.count, 4);
         assert_eq!(rs.digest(), 0x01400082);
 
         rs.rotate(0, 4);
-        assert_eq!(rs.count.0, 4);
+        assert_eq!(rs.count, 4);
         assert_eq!(rs.digest(), 0x014a0086);
 
         rs.rotate(1, 5);
         rs.rotate(2, 6);
         rs.rotate(3, 7);
-        assert_eq!(rs.count.0, 4);
+        assert_eq!(rs.count, 4);
         assert_eq!(rs.digest(), 0x01680092);
 
         rs.roll_out(4);
-        assert_eq!(rs.count.0, 3);
+        assert_eq!(rs.count, 3);
         assert_eq!(rs.digest(), 0x00dc006f);
 
         rs.roll_out(5);
         rs.roll_out(6);
         rs.roll_out(7);
-        assert_eq!(rs.count.0, 0);
+        assert_eq!(rs.count, 0);
         assert_eq!(rs.digest(), 0);
     }
 
     #[test]
+    #[allow(clippy::needless_range_loop)]
     pub fn update() {
         let mut rs = Window::new();
         let mut buf = [0u8; 256];
@@ -131,4 +293,175 @@ mod test {
         rs.update(&buf);
         assert_eq!(rs.digest(), 0x3a009e80);
     }
+
+    #[test]
+    pub fn update_long_buffer() {
+        let buf = pseudo_random(5 << 20, 1);
+        for len in [65535, 65536, 65537, 1 << 20, 5 << 20] {
+            let mut rs = Window::new();
+            rs.update(&buf[..len]);
+            assert_eq!(rs.count, len);
+            assert_eq!(rs.digest(), reference_digest(&buf[..len]), "len {}", len);
+
+            let mut scalar = Window::new();
+            scalar.update_scalar(&buf[..len]);
+            assert_eq!(scalar.count, len);
+            assert_eq!(scalar.digest(), rs.digest(), "len {}", len);
+        }
+    }
+
+    #[test]
+    pub fn roll_long_window() {
+        let buf = pseudo_random(3 << 20, 1);
+        let block_len = (2 << 20) + 3;
+
+        let mut rs = Window::new();
+        for c in &buf[..block_len] {
+            rs.roll_in(*c);
+        }
+        assert_eq!(rs.digest(), reference_digest(&buf[..block_len]));
+
+        for i in 0..(buf.len() - block_len) {
+            rs.rotate(buf[i], buf[i + block_len]);
+        }
+        let tail = &buf[buf.len() - block_len..];
+        assert_eq!(rs.count, block_len);
+        assert_eq!(rs.digest(), reference_digest(tail));
+
+        for (i, c) in tail[..100_000].iter().enumerate() {
+            rs.roll_out(*c);
+            assert_eq!(rs.count, block_len - i - 1);
+        }
+        assert_eq!(rs.digest(), reference_digest(&tail[100_000..]));
+    }
+
+    fn updated(buf: &[u8]) -> Window {
+        let mut rs = Window::new();
+        rs.update(buf);
+        rs
+    }
+
+    /// Checks that rotating a window of `block_len` across `buf` keeps the
+    /// digest equal to that of each window summed afresh, at every `step`th
+    /// offset.
+    fn check_rolling(buf: &[u8], block_len: usize, step: usize) {
+        let mut rs = updated(&buf[..block_len]);
+        for i in block_len..buf.len() {
+            rs.rotate(buf[i - block_len], buf[i]);
+            if (i - block_len + 1).is_multiple_of(step) {
+                let fresh = updated(&buf[i + 1 - block_len..=i]);
+                assert_eq!(rs.count, fresh.count);
+                assert_eq!(rs.digest(), fresh.digest(), "offset {}", i + 1 - block_len);
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn rotate_matches_update(
+            (buf, block_len) in prop::collection::vec(any::<u8>(), 1..2000)
+                .prop_flat_map(|buf| {
+                    let len = buf.len();
+                    (Just(buf), 1..=len)
+                })
+        ) {
+            check_rolling(&buf, block_len, 1);
+        }
+
+        #[test]
+        fn roll_in_and_out_match_update(
+            buf in prop::collection::vec(any::<u8>(), 0..2000),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let cut = cut.index(buf.len() + 1);
+            let mut rs = Window::new();
+            for c in &buf {
+                rs.roll_in(*c);
+            }
+            prop_assert_eq!(rs.digest(), updated(&buf).digest());
+            for c in &buf[..cut] {
+                rs.roll_out(*c);
+            }
+            prop_assert_eq!(rs.count, buf.len() - cut);
+            prop_assert_eq!(rs.digest(), updated(&buf[cut..]).digest());
+        }
+
+        #[test]
+        fn update_is_concatenative(
+            a in prop::collection::vec(any::<u8>(), 0..5000),
+            b in prop::collection::vec(any::<u8>(), 0..5000),
+        ) {
+            let mut rs = updated(&a);
+            rs.update(&b);
+            let whole = updated(&[a.as_slice(), b.as_slice()].concat());
+            prop_assert_eq!(rs.count, whole.count);
+            prop_assert_eq!(rs.digest(), whole.digest());
+        }
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn check_vector_sums(s1: u16, s2: u16, buf: &[u8]) {
+        use super::{sum_bytes_scalar, x86};
+        use std::num::Wrapping;
+
+        let (s1, s2) = (Wrapping(s1), Wrapping(s2));
+        let expected = sum_bytes_scalar(s1, s2, buf);
+        if is_x86_feature_detected!("avx2") {
+            assert_eq!(unsafe { x86::sum_bytes_avx2(s1, s2, buf) }, expected);
+        }
+        if is_x86_feature_detected!("sse2") {
+            assert_eq!(unsafe { x86::sum_bytes_sse2(s1, s2, buf) }, expected);
+        }
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    pub fn vector_sums_long_buffer() {
+        let buf = pseudo_random(3 << 20, 1);
+        check_vector_sums(0, 0, &buf);
+        check_vector_sums(0xffff, 0xfffe, &buf[7..]);
+        check_vector_sums(0, 0, &[0xff; 1 << 20]);
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    proptest! {
+        #[test]
+        fn vector_sums_match_scalar(
+            s1 in any::<u16>(),
+            s2 in any::<u16>(),
+            buf in prop::collection::vec(any::<u8>(), 0..3000),
+        ) {
+            check_vector_sums(s1, s2, &buf);
+        }
+    }
+
+    proptest! {
+        // Windows past 2^16 bytes, where a count kept in 16 bits would wrap.
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn rotate_matches_update_long_window(
+            block_len in 60_000usize..200_000,
+            extra in 1usize..2000,
+            seed in any::<u32>(),
+        ) {
+            let buf = pseudo_random(block_len + extra, seed);
+            check_rolling(&buf, block_len, 97);
+        }
+
+        #[test]
+        fn update_is_concatenative_long(
+            lens in prop::collection::vec(0usize..100_000, 1..6),
+        ) {
+            let buf = pseudo_random(lens.iter().sum(), 1);
+            let mut rs = Window::new();
+            let mut start = 0;
+            for len in &lens {
+                rs.update(&buf[start..start + len]);
+                start += len;
+            }
+            prop_assert_eq!(rs.count, buf.len());
+            prop_assert_eq!(rs.digest(), reference_digest(&buf));
+        }
+    }
 }
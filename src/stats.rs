@@ -0,0 +1,159 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::delta::Command;
+
+/// What a signature, delta or patch operation did, like librsync's `rs_stats_t`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Bytes of the basis, new file or delta read, for signature, delta and patch
+    /// respectively.
+    pub in_bytes: u64,
+
+    pub out_bytes: u64,
+
+    /// Blocks in the signature written, or matched against.
+    pub blocks: u64,
+
+    pub lit_cmds: u64,
+
+    pub lit_bytes: u64,
+
+    pub copy_cmds: u64,
+
+    pub copy_bytes: u64,
+
+    /// Windows whose weak sum matched a block but whose strong sum did not.
+    pub false_matches: u64,
+
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    /// Adds a literal or copy command to the counts.
+    pub fn count_command(&mut self, command: &Command) {
+        match *command {
+            Command::End => {}
+            Command::Literal { len } => {
+                self.lit_cmds += 1;
+                self.lit_bytes += len;
+            }
+            Command::Copy { len, .. } => {
+                self.copy_cmds += 1;
+                self.copy_bytes += len;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "literal[{} cmds, {} bytes] copy[{} cmds, {} bytes, {} false] blocks={} \
+             in-bytes={} out-bytes={} elapsed={:.3}s",
+            self.lit_cmds,
+            self.lit_bytes,
+            self.copy_cmds,
+            self.copy_bytes,
+            self.false_matches,
+            self.blocks,
+            self.in_bytes,
+            self.out_bytes,
+            self.elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// Counts the bytes passing through.
+pub(crate) struct Counted<T> {
+    inner: T,
+
+    pub(crate) bytes: u64,
+}
+
+impl<T> Counted<T> {
+    pub(crate) fn new(inner: T) -> Counted<T> {
+        Counted { inner, bytes: 0 }
+    }
+}
+
+impl<T: Read> Read for Counted<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let l = self.inner.read(buf)?;
+        self.bytes += l as u64;
+        Ok(l)
+    }
+}
+
+impl<T: Write> Write for Counted<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let l = self.inner.write(buf)?;
+        self.bytes += l as u64;
+        Ok(l)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::delta::{generate_delta, Commands};
+    use crate::mksum::{generate_signature, SignatureOptions};
+    use crate::patch::apply_patch;
+    use crate::testutil::pseudo_random;
+    use std::io::Cursor;
+
+    #[test]
+    pub fn statistics() {
+        let basis = pseudo_random(10_000, 12);
+        let mut new = basis.clone();
+        new.splice(5_000..5_000, pseudo_random(100, 13));
+        let options = SignatureOptions::default().with_block_len(1000);
+
+        let mut sig = Vec::new();
+        let stats = generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+        assert_eq!(stats.in_bytes, 10_000);
+        assert_eq!(stats.out_bytes, sig.len() as u64);
+        assert_eq!(stats.blocks, 10);
+
+        let mut delta = Vec::new();
+        let stats = generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+        assert_eq!(stats.in_bytes, 10_100);
+        assert_eq!(stats.out_bytes, delta.len() as u64);
+        assert_eq!(stats.blocks, 10);
+        assert_eq!((stats.lit_cmds, stats.lit_bytes), (1, 100));
+        assert_eq!((stats.copy_cmds, stats.copy_bytes), (2, 10_000));
+        assert_eq!(stats.false_matches, 0);
+
+        let mut out = Vec::new();
+        let patch_stats = apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut out).unwrap();
+        assert_eq!(patch_stats.in_bytes, delta.len() as u64);
+        assert_eq!(patch_stats.out_bytes, 10_100);
+        assert_eq!(
+            (
+                patch_stats.lit_cmds,
+                patch_stats.copy_cmds,
+                patch_stats.copy_bytes
+            ),
+            (1, 2, 10_000)
+        );
+
+        let mut counted = Stats::default();
+        for command in Commands::new(&delta[..]).unwrap() {
+            counted.count_command(&command.unwrap());
+        }
+        assert_eq!(
+            (counted.lit_cmds, counted.lit_bytes),
+            (stats.lit_cmds, stats.lit_bytes)
+        );
+        assert_eq!(
+            (counted.copy_cmds, counted.copy_bytes),
+            (stats.copy_cmds, stats.copy_bytes)
+        );
+    }
+}
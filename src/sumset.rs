@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt};
+use cast::usize;
+
+use crate::error::{Error, Result};
+use crate::mksum::{fill_buffer, SignatureFormat, SignatureOptions, KEY_LEN};
+
+fn read_header_u32(sig: &mut dyn Read) -> Result<u32> {
+    sig.read_u32::<BigEndian>()
+        .map_err(|e| Error::truncated(e, Error::CorruptSignature("truncated header")))
+}
+
+/// Maps weak sums to the numbers of the blocks that have them.
+#[derive(Debug, Default, Clone)]
+pub struct SignatureIndex {
+    blocks: HashMap<u32, Vec<usize>>,
+}
+
+impl SignatureIndex {
+    pub fn candidates(&self, weak: u32) -> &[usize] {
+        self.blocks.get(&weak).map_or(&[], Vec::as_slice)
+    }
+
+    fn insert(&mut self, weak: u32, block: usize) {
+        self.blocks.entry(weak).or_default().push(block);
+    }
+}
+
+/// An in-memory copy of a signature written by `mksum::generate_signature`.
+#[derive(Debug, Clone)]
+pub struct Signature {
+    magic: SignatureFormat,
+
+    block_len: u32,
+
+    strong_len: u32,
+
+    key: Option<[u8; KEY_LEN]>,
+
+    weak: Vec<u32>,
+
+    strong: Vec<u8>,
+
+    index: SignatureIndex,
+}
+
+impl Signature {
+    pub fn load(sig: &mut dyn Read) -> Result<Signature> {
+        let sig = &mut BufReader::new(sig);
+
+        let magic = read_header_u32(sig)?;
+        let magic = SignatureFormat::from_magic(magic).ok_or(Error::BadMagic(magic))?;
+        let block_len = read_header_u32(sig)?;
+        let strong_len = read_header_u32(sig)?;
+        let key = if magic.is_keyed() {
+            let mut key = [0u8; KEY_LEN];
+            sig.read_exact(&mut key)
+                .map_err(|e| Error::truncated(e, Error::CorruptSignature("truncated header")))?;
+            Some(key)
+        } else {
+            None
+        };
+        SignatureOptions {
+            magic,
+            block_len,
+            strong_len,
+            key,
+        }
+        .validate()?;
+
+        let mut signature = Signature {
+            magic,
+            block_len,
+            strong_len,
+            key,
+            weak: Vec::new(),
+            strong: Vec::new(),
+            index: SignatureIndex::default(),
+        };
+        let mut entry = vec![0u8; 4 + usize(strong_len)];
+        loop {
+            let l = fill_buffer(sig, &mut entry)?;
+            if l == 0 {
+                break;
+            } else if l < entry.len() {
+                return Err(Error::CorruptSignature("truncated block entry"));
+            }
+            let weak = u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            signature.index.insert(weak, signature.weak.len());
+            signature.weak.push(weak);
+            signature.strong.extend_from_slice(&entry[4..]);
+        }
+        Ok(signature)
+    }
+
+    pub fn magic(&self) -> SignatureFormat {
+        self.magic
+    }
+
+    pub fn block_len(&self) -> u32 {
+        self.block_len
+    }
+
+    pub fn strong_len(&self) -> u32 {
+        self.strong_len
+    }
+
+    /// The key of a keyed signature.
+    pub fn key(&self) -> Option<&[u8; KEY_LEN]> {
+        self.key.as_ref()
+    }
+
+    /// Number of blocks in the signature.
+    pub fn len(&self) -> usize {
+        self.weak.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weak.is_empty()
+    }
+
+    pub fn weak_sum(&self, block: usize) -> u32 {
+        self.weak[block]
+    }
+
+    pub fn strong_sum(&self, block: usize) -> &[u8] {
+        let l = usize(self.strong_len);
+        &self.strong[block * l..(block + 1) * l]
+    }
+
+    pub fn index(&self) -> &SignatureIndex {
+        &self.index
+    }
+
+    /// Finds a block whose weak sum is `weak` and whose strong sum is a prefix of `strong`,
+    /// trying `preferred` first.
+    pub fn find_block(&self, weak: u32, strong: &[u8], preferred: Option<usize>) -> Option<usize> {
+        let candidates = self.index.candidates(weak);
+        let strong = &strong[..usize(self.strong_len)];
+        if let Some(block) = preferred {
+            if candidates.contains(&block) && self.strong_sum(block) == strong {
+                return Some(block);
+            }
+        }
+        candidates
+            .iter()
+            .copied()
+            .find(|&block| self.strong_sum(block) == strong)
+    }
+
+    /// Writes the header, the block count and the basis sizes that could have
+    /// given it, and with `blocks` each block's weak and strong sums.
+    pub fn dump(&self, out: &mut dyn Write, blocks: bool) -> Result<()> {
+        writeln!(
+            out,
+            "format: {:?} ({:#010x})",
+            self.magic, self.magic as u32
+        )?;
+        writeln!(out, "block_len: {}", self.block_len)?;
+        writeln!(out, "strong_len: {}", self.strong_len)?;
+        if let Some(key) = &self.key {
+            write!(out, "key: ")?;
+            write_hex(out, key)?;
+            writeln!(out)?;
+        }
+        writeln!(out, "blocks: {}", self.len())?;
+        let n = self.len() as u64;
+        if n == 0 {
+            writeln!(out, "basis_len: 0")?;
+        } else {
+            let block_len = u64::from(self.block_len);
+            writeln!(
+                out,
+                "basis_len: {}..={}",
+                (n - 1) * block_len + 1,
+                n * block_len
+            )?;
+        }
+        if blocks {
+            for block in 0..self.len() {
+                write!(out, "{} {:08x} ", block, self.weak_sum(block))?;
+                write_hex(out, self.strong_sum(block))?;
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_hex(out: &mut dyn Write, bytes: &[u8]) -> Result<()> {
+    for b in bytes {
+        write!(out, "{:02x}", b)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mksum::generate_signature;
+
+    fn signature_on_arrays(in_buf: &[u8], block_len: u32, strong_len: u32) -> Vec<u8> {
+        let options = SignatureOptions {
+            block_len,
+            ..SignatureOptions::default()
+        }
+        .with_strong_len(strong_len);
+        let mut out_buf = Vec::new();
+        generate_signature(&mut &in_buf[..], &options, &mut out_buf).unwrap();
+        out_buf
+    }
+
+    #[test]
+    pub fn load_empty_signature() {
+        let sig = signature_on_arrays(&[], 2048, 32);
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        assert_eq!(sig.magic(), SignatureFormat::Blake2Sig);
+        assert_eq!(sig.block_len(), 2048);
+        assert_eq!(sig.strong_len(), 32);
+        assert!(sig.is_empty());
+    }
+
+    #[test]
+    pub fn load_blocks_and_index() {
+        let sig = signature_on_arrays(b"abcdabcdab", 4, 8);
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        assert_eq!(sig.len(), 3);
+        assert_eq!(sig.weak_sum(0), sig.weak_sum(1));
+        assert_ne!(sig.weak_sum(0), sig.weak_sum(2));
+        assert_eq!(sig.strong_sum(0), sig.strong_sum(1));
+        assert_eq!(sig.strong_sum(2).len(), 8);
+        assert_eq!(sig.index().candidates(sig.weak_sum(0)), &[0, 1]);
+        assert_eq!(sig.index().candidates(sig.weak_sum(2)), &[2]);
+        assert_eq!(sig.index().candidates(0), &[] as &[usize]);
+
+        let mut strong = sig.strong_sum(0).to_vec();
+        strong.extend_from_slice(&[0; 24]);
+        assert_eq!(sig.find_block(sig.weak_sum(0), &strong, None), Some(0));
+        assert_eq!(sig.find_block(sig.weak_sum(0), &strong, Some(1)), Some(1));
+        assert_eq!(sig.find_block(sig.weak_sum(2), &strong, None), None);
+    }
+
+    #[test]
+    pub fn corrupt_signatures() {
+        let sig = signature_on_arrays(b"Hello world\n", 4, 8);
+        let err = Signature::load(&mut &sig[..sig.len() - 1]).unwrap_err();
+        assert!(matches!(err, Error::CorruptSignature(_)));
+
+        let err = Signature::load(&mut &sig[..6]).unwrap_err();
+        assert!(matches!(err, Error::CorruptSignature(_)));
+
+        let mut bad = sig.clone();
+        bad[3] = 0x99;
+        let err = Signature::load(&mut &bad[..]).unwrap_err();
+        assert!(matches!(err, Error::BadMagic(0x72730199)));
+
+        let mut bad = sig.clone();
+        bad[7] = 0;
+        let err = Signature::load(&mut &bad[..]).unwrap_err();
+        assert!(matches!(err, Error::InvalidBlockLen(0)));
+
+        // Loading must not trust a block length it would later have to buffer.
+        let mut bad = sig.clone();
+        bad[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        let err = Signature::load(&mut &bad[..]).unwrap_err();
+        assert!(matches!(err, Error::InvalidBlockLen(u32::MAX)));
+
+        let mut bad = sig.clone();
+        bad[11] = 33;
+        let err = Signature::load(&mut &bad[..]).unwrap_err();
+        assert!(matches!(err, Error::InvalidStrongLen(33)));
+
+        // MD4 sums are only 16 bytes long.
+        let mut bad = sig;
+        bad[3] = 0x36;
+        bad[11] = 17;
+        let err = Signature::load(&mut &bad[..]).unwrap_err();
+        assert!(matches!(err, Error::InvalidStrongLen(17)));
+    }
+
+    #[test]
+    pub fn load_md4_signature() {
+        let options = SignatureOptions {
+            magic: SignatureFormat::Md4Sig,
+            block_len: 4,
+            strong_len: 16,
+            key: None,
+        };
+        let mut sig = Vec::new();
+        generate_signature(&mut &b"Hello world\n"[..], &options, &mut sig).unwrap();
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        assert_eq!(sig.magic(), SignatureFormat::Md4Sig);
+        assert_eq!(sig.strong_len(), 16);
+        assert_eq!(sig.len(), 3);
+        assert_eq!(sig.key(), None);
+    }
+
+    #[test]
+    pub fn load_keyed_signature() {
+        let options = SignatureOptions::default()
+            .with_magic(SignatureFormat::RkKeyedBlake2Sig)
+            .with_block_len(4)
+            .with_strong_len(8)
+            .with_key([7; KEY_LEN]);
+        let mut sig = Vec::new();
+        generate_signature(&mut &b"Hello world\n"[..], &options, &mut sig).unwrap();
+        let loaded = Signature::load(&mut &sig[..]).unwrap();
+        assert_eq!(loaded.magic(), SignatureFormat::RkKeyedBlake2Sig);
+        assert_eq!(loaded.key(), Some(&[7; KEY_LEN]));
+        assert_eq!(loaded.len(), 3);
+
+        let err = Signature::load(&mut &sig[..12 + KEY_LEN - 1]).unwrap_err();
+        assert!(matches!(err, Error::CorruptSignature("truncated header")));
+    }
+
+    #[test]
+    pub fn dump() {
+        let options = SignatureOptions {
+            magic: SignatureFormat::Md4Sig,
+            block_len: 4,
+            strong_len: 4,
+            key: None,
+        };
+        let mut sig = Vec::new();
+        generate_signature(&mut &b"Hello world\n"[..], &options, &mut sig).unwrap();
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        let mut out = Vec::new();
+        sig.dump(&mut out, true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[..5],
+            [
+                "format: Md4Sig (0x72730136)",
+                "block_len: 4",
+                "strong_len: 4",
+                "blocks: 3",
+                "basis_len: 9..=12",
+            ]
+        );
+        assert_eq!(lines.len(), 8);
+        let strong: String = sig
+            .strong_sum(2)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(lines[7], format!("2 {:08x} {}", sig.weak_sum(2), strong));
+
+        let sig = signature_on_arrays(&[], 2048, 32);
+        let sig = Signature::load(&mut &sig[..]).unwrap();
+        let mut out = Vec::new();
+        sig.dump(&mut out, true).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("blocks: 0\nbasis_len: 0\n"));
+    }
+}
@@ -0,0 +1,40 @@
+//! Fixtures shared by the unit tests.
+
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::delta::generate_delta;
+use crate::mksum::{generate_signature, SignatureOptions};
+use crate::patch::apply_patch;
+use crate::Stats;
+
+/// Bytes from a linear congruential generator, so tests see the same data on every run.
+pub fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+    let mut x = seed;
+    (0..len)
+        .map(|_| {
+            x = x.wrapping_mul(1103515245).wrapping_add(12345);
+            (x >> 16) as u8
+        })
+        .collect()
+}
+
+/// `stats` with the one field that differs between otherwise identical runs cleared.
+pub fn without_elapsed(stats: Stats) -> Stats {
+    Stats {
+        elapsed: Duration::ZERO,
+        ..stats
+    }
+}
+
+/// Runs `basis` and `new` through signature, delta and patch, returning the patched file.
+pub fn round_trip_with(basis: &[u8], new: &[u8], options: &SignatureOptions) -> Vec<u8> {
+    let mut sig = Vec::new();
+    generate_signature(&mut &basis[..], options, &mut sig).unwrap();
+    let mut delta = Vec::new();
+    generate_delta(&mut &sig[..], &mut &new[..], &mut delta).unwrap();
+
+    let mut out_buf = Vec::new();
+    apply_patch(&mut Cursor::new(basis), &mut &delta[..], &mut out_buf).unwrap();
+    out_buf
+}
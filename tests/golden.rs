@@ -0,0 +1,74 @@
+//! Byte-for-byte checks against the fixtures in tests/golden; see its README.
+
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use rdiff::delta::generate_delta;
+use rdiff::mksum::{generate_signature, SignatureFormat, SignatureOptions};
+use rdiff::patch::apply_patch;
+
+const BLOCK_LENS: [u32; 3] = [64, 512, 2048];
+
+const FORMATS: [(&str, &str, SignatureFormat, [u32; 2]); 4] = [
+    ("md4", "rollsum", SignatureFormat::Md4Sig, [8, 16]),
+    ("blake2", "rollsum", SignatureFormat::Blake2Sig, [8, 32]),
+    ("md4", "rabinkarp", SignatureFormat::RkMd4Sig, [8, 16]),
+    ("blake2", "rabinkarp", SignatureFormat::RkBlake2Sig, [8, 32]),
+];
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path: PathBuf = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", name]
+        .iter()
+        .collect();
+    fs::read(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+}
+
+#[test]
+pub fn signatures() {
+    let basis = fixture("basis");
+    for block_len in BLOCK_LENS {
+        for (hash, rollsum, magic, strong_lens) in FORMATS {
+            for strong_len in strong_lens {
+                let name = format!("sig-{}-{}-b{}-s{}", hash, rollsum, block_len, strong_len);
+                let options = SignatureOptions {
+                    magic,
+                    block_len,
+                    strong_len,
+                    key: None,
+                };
+                let mut sig = Vec::new();
+                generate_signature(&mut &basis[..], &options, &mut sig).unwrap();
+                assert!(sig == fixture(&name), "{} differs", name);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn deltas() {
+    let new = fixture("new");
+    for block_len in BLOCK_LENS {
+        let expected = fixture(&format!("delta-b{}", block_len));
+        for (hash, rollsum, _, strong_lens) in FORMATS {
+            for strong_len in strong_lens {
+                let name = format!("sig-{}-{}-b{}-s{}", hash, rollsum, block_len, strong_len);
+                let mut delta = Vec::new();
+                generate_delta(&mut &fixture(&name)[..], &mut &new[..], &mut delta).unwrap();
+                assert!(delta == expected, "delta from {} differs", name);
+            }
+        }
+    }
+}
+
+#[test]
+pub fn patches() {
+    let basis = fixture("basis");
+    let new = fixture("new");
+    for block_len in BLOCK_LENS {
+        let delta = fixture(&format!("delta-b{}", block_len));
+        let mut out = Vec::new();
+        apply_patch(&mut Cursor::new(&basis), &mut &delta[..], &mut out).unwrap();
+        assert!(out == new, "patching with delta-b{} differs", block_len);
+    }
+}
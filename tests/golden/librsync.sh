@@ -0,0 +1,29 @@
+#!/bin/sh
+# Rewrites the fixtures with librsync's rdiff; run from this directory.
+set -e
+RDIFF=${RDIFF:-rdiff}
+
+for b in 64 512 2048; do
+    first=
+    for format in "md4 rollsum 8 16" "blake2 rollsum 8 32" \
+                  "md4 rabinkarp 8 16" "blake2 rabinkarp 8 32"; do
+        set -- $format
+        hash=$1 rollsum=$2
+        for s in $3 $4; do
+            sig=sig-$hash-$rollsum-b$b-s$s
+            "$RDIFF" -f -H "$hash" -R "$rollsum" -b "$b" -S "$s" signature basis "$sig"
+            if [ -z "$first" ]; then
+                "$RDIFF" -f delta "$sig" new "delta-b$b"
+                "$RDIFF" -f patch basis "delta-b$b" new.tmp
+                cmp new.tmp new
+                rm new.tmp
+                first=$sig
+            else
+                # Every signature of the same block length should give the same delta.
+                "$RDIFF" -f delta "$sig" new delta.tmp
+                cmp delta.tmp "delta-b$b" || echo "delta from $sig differs from $first"
+                rm delta.tmp
+            fi
+        done
+    done
+done